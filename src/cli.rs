@@ -0,0 +1,223 @@
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+use getopts::Options;
+use irc_lib::client::data::Config;
+
+/// Command-line options. These take precedence over the config file.
+pub struct Args {
+    pub config: Option<PathBuf>,
+    pub server: Option<String>,
+    pub port: Option<u16>,
+    pub nick: Option<String>,
+    pub tls: Option<bool>,
+    pub join: Option<Vec<String>>,
+    pub no_connect: bool,
+}
+
+/// Parses the command line. Exits on `--help`, `--version` and errors.
+pub fn parse() -> Args {
+    let args: Vec<String> = env::args().collect();
+    let program = args.get(0).cloned().unwrap_or_else(|| String::from("simpleirc"));
+
+    let mut options = Options::new();
+    options.optopt("c", "config", "read the config from PATH", "PATH");
+    options.optopt("s", "server", "connect to HOST instead of the configured network", "HOST[:PORT]");
+    options.optopt("n", "nick", "use NICK as the nickname", "NICK");
+    options.optflag("", "tls", "connect using TLS");
+    options.optflag("", "no-tls", "connect without TLS");
+    options.optopt("j", "join", "join these channels instead of the configured ones", "#A,#B");
+    options.optflag("", "no-connect", "start without connecting; use /connect later");
+    options.optflag("V", "version", "print the version and exit");
+    options.optflag("h", "help", "print this help and exit");
+
+    let matches = match options.parse(&args[1..]) {
+        Ok(matches) => matches,
+        Err(error) => fail(&program, &error.to_string()),
+    };
+    if matches.opt_present("help") {
+        let brief = format!("Usage: {} [options]", program);
+        print!("{}", options.usage(&brief));
+        process::exit(0);
+    }
+    if matches.opt_present("version") {
+        println!("simpleirc {}", env!("CARGO_PKG_VERSION"));
+        process::exit(0);
+    }
+    if matches.opt_present("tls") && matches.opt_present("no-tls") {
+        fail(&program, "--tls and --no-tls can't be used together");
+    }
+
+    let (server, port) = match matches.opt_str("server") {
+        Some(server) => {
+            let (host, port) = split_host_port(&server)
+                .unwrap_or_else(|| fail(&program, &format!("invalid server: {}", server)));
+            (Some(host), port)
+        }
+        None => (None, None),
+    };
+    let tls = if matches.opt_present("tls") {
+        Some(true)
+    } else if matches.opt_present("no-tls") {
+        Some(false)
+    } else {
+        None
+    };
+    let join = matches.opt_str("join").map(|channels| {
+        channels.split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(String::from)
+            .collect()
+    });
+
+    Args {
+        config: matches.opt_str("config").map(PathBuf::from),
+        server: server,
+        port: port,
+        nick: matches.opt_str("nick"),
+        tls: tls,
+        join: join,
+        no_connect: matches.opt_present("no-connect"),
+    }
+}
+
+impl Args {
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(ref server) = self.server {
+            config.server = Some(server.clone());
+            // The configured port, passwords and channels belong to the
+            // configured server. Its passwords mustn't go to another one.
+            config.port = self.port;
+            config.password = None;
+            config.nick_password = None;
+            config.channels = None;
+        }
+        if let Some(ref nick) = self.nick {
+            config.nickname = Some(nick.clone());
+        }
+        if let Some(tls) = self.tls {
+            config.use_ssl = Some(tls);
+        }
+        if let Some(ref join) = self.join {
+            config.channels = Some(join.clone());
+        }
+    }
+}
+
+fn split_host_port(server: &str) -> Option<(String, Option<u16>)> {
+    // An IPv6 address has colons of its own, so the port has to come after
+    // the closing bracket. Without brackets, it can't have a port.
+    let port_start = if server.starts_with('[') {
+        server.find(']').and_then(|i| if server[i + 1..].starts_with(':') { Some(i + 1) } else { None })
+    } else if server.matches(':').count() == 1 {
+        server.rfind(':')
+    } else {
+        None
+    };
+    let (host, port) = match port_start {
+        Some(i) => match server[i + 1..].parse() {
+            Ok(port) => (&server[..i], Some(port)),
+            Err(_) => return None,
+        },
+        None => (server, None),
+    };
+    let host = host.trim_matches(&['[', ']'][..]);
+    if host.is_empty() {
+        None
+    } else {
+        Some((String::from(host), port))
+    }
+}
+
+fn fail(program: &str, message: &str) -> ! {
+    eprintln!("{}: {}", program, message);
+    eprintln!("Try '{} --help' for more information.", program);
+    process::exit(2);
+}
+
+#[cfg(test)]
+mod tests {
+    use irc_lib::client::data::Config;
+
+    use super::{Args, split_host_port};
+
+    fn args(server: Option<&str>) -> Args {
+        Args {
+            config: None,
+            server: server.map(String::from),
+            port: None,
+            nick: None,
+            tls: None,
+            join: None,
+            no_connect: false,
+        }
+    }
+
+    fn configured() -> Config {
+        Config {
+            server: Some(String::from("irc.libera.chat")),
+            port: Some(6697),
+            password: Some(String::from("cmd:pass show irc/libera")),
+            nick_password: Some(String::from("hunter2")),
+            channels: Some(vec![String::from("#rust")]),
+            ..Config::default()
+        }
+    }
+
+    fn host(host: &str, port: Option<u16>) -> Option<(String, Option<u16>)> {
+        Some((String::from(host), port))
+    }
+
+    #[test]
+    fn splits_hosts_and_ports() {
+        assert_eq!(split_host_port("irc.libera.chat"), host("irc.libera.chat", None));
+        assert_eq!(split_host_port("irc.libera.chat:6697"), host("irc.libera.chat", Some(6697)));
+        assert_eq!(split_host_port("127.0.0.1:6667"), host("127.0.0.1", Some(6667)));
+    }
+
+    #[test]
+    fn splits_ipv6_addresses() {
+        assert_eq!(split_host_port("[::1]"), host("::1", None));
+        assert_eq!(split_host_port("[2001:db8::1]:6697"), host("2001:db8::1", Some(6697)));
+        assert_eq!(split_host_port("::1"), host("::1", None));
+        assert_eq!(split_host_port("2001:db8::1"), host("2001:db8::1", None));
+    }
+
+    #[test]
+    fn rejects_bad_ports_and_missing_hosts() {
+        assert_eq!(split_host_port("irc.libera.chat:"), None);
+        assert_eq!(split_host_port("irc.libera.chat:ircs"), None);
+        assert_eq!(split_host_port("irc.libera.chat:70000"), None);
+        assert_eq!(split_host_port("[::1]:"), None);
+        assert_eq!(split_host_port(":6667"), None);
+        assert_eq!(split_host_port("[]"), None);
+    }
+
+    #[test]
+    fn keeps_the_configured_network_without_server() {
+        let mut config = configured();
+        args(None).apply(&mut config);
+        assert_eq!(config, configured());
+    }
+
+    #[test]
+    fn another_server_gets_none_of_the_networks_secrets() {
+        let mut config = configured();
+        args(Some("irc.example.com")).apply(&mut config);
+        assert_eq!(config.server, Some(String::from("irc.example.com")));
+        assert_eq!(config.port, None);
+        assert_eq!(config.password, None);
+        assert_eq!(config.nick_password, None);
+        assert_eq!(config.channels, None);
+    }
+
+    #[test]
+    fn joins_the_given_channels_on_another_server() {
+        let mut config = configured();
+        let args = Args { join: Some(vec![String::from("#test")]), ..args(Some("irc.example.com")) };
+        args.apply(&mut config);
+        assert_eq!(config.channels, Some(vec![String::from("#test")]));
+    }
+}
@@ -0,0 +1,715 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use irc_lib::client::data::Config;
+use toml::{self, Value};
+
+use irc::misc::mask_matches;
+use tui::{displayarea, keymap, statusbar, theme};
+
+const APP_NAME: &'static str = "simpleirc";
+const FILE_NAME: &'static str = "config.toml";
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ClientConfig {
+    /// The network to connect to. Defaults to the first one.
+    pub network: Option<String>,
+    #[serde(default)]
+    pub networks: BTreeMap<String, Network>,
+    #[serde(default)]
+    pub identities: BTreeMap<String, Identity>,
+    #[serde(default)]
+    pub ui: Ui,
+    #[serde(default)]
+    pub logging: Logging,
+    #[serde(default)]
+    pub notifications: Notifications,
+    #[serde(default)]
+    pub keybindings: BTreeMap<String, String>,
+    /// Messages from users matching these masks aren't shown. A mask is a
+    /// nick or `nick!user@host`, and may contain `*` and `?`.
+    #[serde(default)]
+    pub ignores: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Network {
+    pub server: String,
+    pub port: Option<u16>,
+    #[serde(default = "default_true")]
+    pub tls: bool,
+    pub password: Option<String>,
+    #[serde(default = "default_identity")]
+    pub identity: String,
+    #[serde(default)]
+    pub channels: Vec<String>,
+    pub encoding: Option<String>,
+    pub umodes: Option<String>,
+    pub ping_time: Option<u32>,
+    pub ping_timeout: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Identity {
+    pub nick: String,
+    #[serde(default)]
+    pub alt_nicks: Vec<String>,
+    pub username: Option<String>,
+    pub realname: Option<String>,
+    pub nick_password: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Ui {
+    pub timestamp_format: String,
+    pub scrollback: usize,
+    pub send_typing: bool,
+    /// Minutes without input before going away. 0 disables auto-away.
+    pub auto_away_minutes: u64,
+    pub auto_away_message: String,
+    /// Edit the entry line with vi keys instead of emacs ones.
+    pub vi_mode: bool,
+    /// How many rows the entry line can grow to, from 1 to 3. With 1, long
+    /// lines scroll sideways instead.
+    pub entry_rows: u16,
+    /// Show the topic of the current channel at the top of the screen.
+    pub topic_bar: bool,
+    /// One of the built-in themes, which `styles` can change.
+    pub theme: String,
+    /// Styles for parts of the screen, such as `bold yellow on blue`.
+    /// Colours are names, such as `red` or `light_blue`, 256-colour palette
+    /// indices or `#rrggbb`.
+    pub styles: BTreeMap<String, String>,
+    /// `16`, `256` or `truecolor`, or `auto` to go by `$COLORTERM` and
+    /// `$TERM`. Colours the terminal can't show are drawn in the nearest one
+    /// it can.
+    pub color_depth: String,
+    pub nick_colors: NickColors,
+    pub messages: MessageFormat,
+    pub sidebar: Sidebar,
+    pub statusbar: StatusBar,
+}
+
+impl Default for Ui {
+    fn default() -> Ui {
+        Ui {
+            timestamp_format: String::from("%H:%M"),
+            scrollback: 1000,
+            send_typing: true,
+            auto_away_minutes: 0,
+            auto_away_message: String::from("Auto-away"),
+            vi_mode: false,
+            entry_rows: 1,
+            topic_bar: true,
+            theme: String::from("default"),
+            styles: BTreeMap::new(),
+            color_depth: String::from("auto"),
+            nick_colors: NickColors::default(),
+            messages: MessageFormat::default(),
+            sidebar: Sidebar::default(),
+            statusbar: StatusBar::default(),
+        }
+    }
+}
+
+/// Other people's nicks get a colour from `palette`, picked by hashing the
+/// nick, unless one is pinned to it.
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NickColors {
+    pub enabled: bool,
+    pub palette: Vec<String>,
+    /// The terminal's background colour. Palette colours too close to it in
+    /// brightness aren't used. Empty to go by the theme.
+    pub background: String,
+    /// Colours for particular nicks.
+    pub pinned: BTreeMap<String, String>,
+}
+
+impl Default for NickColors {
+    fn default() -> NickColors {
+        let palette = [
+            "red", "green", "yellow", "blue", "magenta", "cyan", "light_red",
+            "light_green", "light_yellow", "light_blue", "light_magenta", "light_cyan",
+        ];
+        NickColors {
+            enabled: true,
+            palette: palette.iter().map(|&x| String::from(x)).collect(),
+            background: String::new(),
+            pinned: BTreeMap::new(),
+        }
+    }
+}
+
+/// How messages are shown, as templates with `{nick}`, `{target}` and
+/// `{message}` in them.
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MessageFormat {
+    pub message: String,
+    pub notice: String,
+    /// For `/me`.
+    pub action: String,
+    /// The width of a column that nicks are right-aligned in, so that
+    /// messages line up. 0 for no column.
+    pub nick_width: usize,
+    /// Put at the end of nicks too long for the column.
+    pub truncation_marker: String,
+    /// Show the nick's channel mode, such as `@` for an operator, before it.
+    pub mode_prefix: bool,
+}
+
+impl Default for MessageFormat {
+    fn default() -> MessageFormat {
+        MessageFormat {
+            message: String::from("<{nick}> {message}"),
+            notice: String::from("-{nick}- {message}"),
+            action: String::from("* {nick} {message}"),
+            nick_width: 0,
+            truncation_marker: String::from("…"),
+            mode_prefix: true,
+        }
+    }
+}
+
+/// The status bar is one or two lines, each with items on the left and on
+/// the right. Items are written as `{name}` among other text, and are left
+/// out along with a following space when they have nothing to show.
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatusBar {
+    pub lines: Vec<StatusLine>,
+    /// Styles for items, by item name.
+    pub colors: BTreeMap<String, String>,
+    /// How `{time}` is shown, in strftime format.
+    pub time_format: String,
+    /// Seconds of lag from which `{lag}` is shown in the `lag_warning`
+    /// style, and then the `lag_alert` one.
+    pub lag_warning: u64,
+    pub lag_alert: u64,
+}
+
+impl Default for StatusBar {
+    fn default() -> StatusBar {
+        StatusBar {
+            lines: vec![StatusLine {
+                left: String::from("[{window}] {nick} {channel_modes} {away} {activity} {typing}"),
+                right: String::from("{scroll} {queue} {lag} {time}"),
+            }],
+            colors: BTreeMap::new(),
+            time_format: String::from("%H:%M"),
+            lag_warning: 2,
+            lag_alert: 5,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatusLine {
+    pub left: String,
+    pub right: String,
+}
+
+/// The window list down the left of the screen.
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sidebar {
+    pub enabled: bool,
+    pub width: u16,
+    /// Hide the sidebar when the terminal is narrower than this many
+    /// columns. 0 never hides it.
+    pub auto_hide_below: u16,
+    /// Select windows by clicking on them. This stops the terminal from
+    /// selecting text, unless Shift is held.
+    pub mouse: bool,
+}
+
+impl Default for Sidebar {
+    fn default() -> Sidebar {
+        Sidebar {
+            enabled: false,
+            width: 20,
+            auto_hide_below: 80,
+            mouse: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Logging {
+    pub enabled: bool,
+    pub directory: Option<PathBuf>,
+}
+
+impl Logging {
+    /// The configured directory, or `$XDG_DATA_HOME/simpleirc/logs`.
+    pub fn directory(&self) -> PathBuf {
+        if let Some(ref directory) = self.directory {
+            return directory.clone();
+        }
+        data_dir().join("logs")
+    }
+}
+
+/// Where the client keeps its files, `$XDG_DATA_HOME/simpleirc`.
+pub fn data_dir() -> PathBuf {
+    let data_home = match env::var_os("XDG_DATA_HOME") {
+        Some(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => env::home_dir().unwrap_or_else(PathBuf::new).join(".local/share"),
+    };
+    data_home.join(APP_NAME)
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Notifications {
+    /// Messages containing any of these words, case-insensitively, are
+    /// highlights.
+    pub highlight_words: Vec<String>,
+    /// Ring the terminal bell on highlights.
+    pub bell: bool,
+}
+
+impl Default for Notifications {
+    fn default() -> Notifications {
+        Notifications {
+            highlight_words: Vec::new(),
+            bell: true,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_identity() -> String {
+    String::from("default")
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    path: PathBuf,
+    line: Option<usize>,
+    message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.path.display(), line, self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+impl ClientConfig {
+    /// Loads the config from `path`, or from the XDG config directories if
+    /// no path is given. Without a config file, everything is left at its
+    /// default, so that the client can be run with command-line options only.
+    pub fn load(path: Option<&Path>) -> Result<ClientConfig, ConfigError> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => match find_config() {
+                Some(path) => path,
+                None => return Ok(ClientConfig::default()),
+            },
+        };
+        let mut source = String::new();
+        let read = File::open(&path).and_then(|mut file| file.read_to_string(&mut source));
+        if let Err(error) = read {
+            return Err(ConfigError { path: path, line: None, message: error.to_string() });
+        }
+        let config: ClientConfig = match toml::from_str(&source) {
+            Ok(config) => config,
+            Err(error) => {
+                // Schema errors don't carry a position, only the key they're
+                // about, so we look for the key ourselves.
+                let line = match error.line_col() {
+                    Some((line, _)) => Some(line + 1),
+                    None => error_key(&error.to_string()).and_then(|key| find_key(&source, &key)),
+                };
+                return Err(ConfigError { path: path, line: line, message: error.to_string() });
+            }
+        };
+        match config.validate(&source) {
+            Ok(()) => Ok(config),
+            Err((line, message)) => Err(ConfigError { path: path, line: line, message: message }),
+        }
+    }
+
+    fn validate(&self, source: &str) -> Result<(), (Option<usize>, String)> {
+        if let Some(ref network) = self.network {
+            if !self.networks.contains_key(network) {
+                return Err((find_key(source, "network"), format!("unknown network `{}`", network)));
+            }
+        }
+        for (name, network) in &self.networks {
+            if !self.identities.contains_key(&network.identity) {
+                let key = format!("networks.{}.identity", name);
+                let line = find_key(source, &key).or_else(|| find_key(source, &format!("networks.{}", name)));
+                return Err((line, format!("network `{}` uses unknown identity `{}`", name, network.identity)));
+            }
+        }
+        let lines = &self.ui.statusbar.lines;
+        if lines.is_empty() || lines.len() > 2 {
+            return Err((find_key(source, "ui.statusbar.lines"), String::from("the status bar must have one or two lines")));
+        }
+        for (i, line) in lines.iter().enumerate() {
+            for &(side, template) in &[("left", &line.left), ("right", &line.right)] {
+                if let Err(message) = statusbar::check_template(template) {
+                    let key = format!("ui.statusbar.lines.{}.{}", i, side);
+                    let line = find_key(source, &key).or_else(|| find_key(source, "ui.statusbar.lines"));
+                    return Err((line, message));
+                }
+            }
+        }
+        for (item, style) in &self.ui.statusbar.colors {
+            if let Err(message) = theme::Style::parse(style) {
+                let key = format!("ui.statusbar.colors.{}", item);
+                return Err((find_key(source, &key), format!("{} for `{}`", message, item)));
+            }
+        }
+        if !theme::exists(&self.ui.theme) {
+            let message = format!("unknown theme `{}`, the themes are {}", self.ui.theme, theme::names().join(", "));
+            return Err((find_key(source, "ui.theme"), message));
+        }
+        for (element, style) in &self.ui.styles {
+            let key = format!("ui.styles.{}", element);
+            if !theme::ELEMENTS.contains(&&element[..]) {
+                return Err((find_key(source, &key), format!("unknown element `{}`", element)));
+            }
+            if let Err(message) = theme::Style::parse(style) {
+                return Err((find_key(source, &key), format!("{} for `{}`", message, element)));
+            }
+        }
+        let nick_colors = &self.ui.nick_colors;
+        if nick_colors.palette.is_empty() {
+            return Err((find_key(source, "ui.nick_colors.palette"), String::from("the nick colour palette is empty")));
+        }
+        let mut colors: Vec<(&str, &String)> = nick_colors.palette.iter().map(|x| ("palette", x)).collect();
+        if !nick_colors.background.is_empty() {
+            colors.push(("background", &nick_colors.background));
+        }
+        for (name, color) in colors {
+            if theme::Color::parse(color).is_none() {
+                let key = format!("ui.nick_colors.{}", name);
+                return Err((find_key(source, &key), format!("unknown colour `{}`", color)));
+            }
+        }
+        for (nick, color) in &nick_colors.pinned {
+            if theme::Color::parse(color).is_none() {
+                let key = format!("ui.nick_colors.pinned.{}", nick);
+                return Err((find_key(source, &key), format!("unknown colour `{}` for `{}`", color, nick)));
+            }
+        }
+        let messages = &self.ui.messages;
+        let templates = [("message", &messages.message), ("notice", &messages.notice), ("action", &messages.action)];
+        for &(name, template) in &templates {
+            if let Err(message) = displayarea::check_template(template) {
+                return Err((find_key(source, &format!("ui.messages.{}", name)), message));
+            }
+        }
+        match &self.ui.color_depth[..] {
+            "auto" | "16" | "256" | "truecolor" => {}
+            _ => {
+                let message = String::from("color_depth must be `auto`, `16`, `256` or `truecolor`");
+                return Err((find_key(source, "ui.color_depth"), message));
+            }
+        }
+        for (key, action) in &self.keybindings {
+            if let Err(message) = keymap::check_binding(key, action) {
+                let line = source.lines().position(|line| {
+                    let name = line.split('=').next().unwrap_or("").trim().trim_matches('"');
+                    name == key
+                });
+                return Err((line.map(|x| x + 1), message));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn network_name(&self) -> &str {
+        match self.network {
+            Some(ref network) => &network[..],
+            None => self.networks.keys().next().map(|x| &x[..]).unwrap_or(""),
+        }
+    }
+
+    pub fn is_ignored(&self, prefix: &str) -> bool {
+        self.ignores.iter().any(|mask| mask_matches(mask, prefix))
+    }
+
+    /// Builds the irc crate's config for the selected network. Fields are
+    /// left empty if there's no network configured.
+    pub fn irc_config(&self) -> Config {
+        let mut config = Config::default();
+        let network = match self.networks.get(self.network_name()) {
+            Some(network) => network,
+            None => return config,
+        };
+        config.server = Some(network.server.clone());
+        config.port = network.port;
+        config.password = network.password.clone();
+        config.use_ssl = Some(network.tls);
+        config.encoding = network.encoding.clone();
+        config.channels = Some(network.channels.clone());
+        config.umodes = network.umodes.clone();
+        config.ping_time = network.ping_time;
+        config.ping_timeout = network.ping_timeout;
+        if let Some(identity) = self.identities.get(&network.identity) {
+            config.nickname = Some(identity.nick.clone());
+            config.nick_password = identity.nick_password.clone();
+            config.alt_nicks = Some(identity.alt_nicks.clone());
+            config.username = identity.username.clone();
+            config.realname = identity.realname.clone();
+        }
+        config
+    }
+}
+
+/// A setting that differs between two configs. `old` is `None` for an added
+/// setting and `new` is `None` for a removed one.
+pub struct Change {
+    pub key: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+impl Change {
+    /// Connection settings are only read when connecting.
+    pub fn needs_reconnect(&self) -> bool {
+        self.key == "network" || self.key.starts_with("networks.") || self.key.starts_with("identities.")
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.old, &self.new) {
+            (&Some(ref old), &Some(ref new)) => write!(f, "{}: {} -> {}", self.key, old, new),
+            (&None, &Some(ref new)) => write!(f, "{}: added {}", self.key, new),
+            (&Some(ref old), &None) => write!(f, "{}: removed {}", self.key, old),
+            (&None, &None) => write!(f, "{}", self.key),
+        }
+    }
+}
+
+/// Lists the settings that differ between two configs, by dotted key.
+/// Passwords are compared but not shown.
+pub fn diff(old: &ClientConfig, new: &ClientConfig) -> Vec<Change> {
+    let mut old_values = BTreeMap::new();
+    let mut new_values = BTreeMap::new();
+    if let Ok(value) = Value::try_from(old) {
+        flatten(&value, String::new(), &mut old_values);
+    }
+    if let Ok(value) = Value::try_from(new) {
+        flatten(&value, String::new(), &mut new_values);
+    }
+    let mut changes = Vec::new();
+    for (key, old) in &old_values {
+        match new_values.get(key) {
+            Some(new) if new == old => {}
+            new => changes.push(Change { key: key.clone(), old: Some(old.clone()), new: new.cloned() }),
+        }
+    }
+    for (key, new) in &new_values {
+        if !old_values.contains_key(key) {
+            changes.push(Change { key: key.clone(), old: None, new: Some(new.clone()) });
+        }
+    }
+    changes.sort_by(|a, b| a.key.cmp(&b.key));
+    for change in &mut changes {
+        if change.key.ends_with("password") {
+            change.old = change.old.as_ref().map(|_| String::from("(hidden)"));
+            change.new = change.new.as_ref().map(|_| String::from("(hidden)"));
+        }
+    }
+    changes
+}
+
+/// Collects the leaves of a toml tree by dotted key. Arrays are kept whole.
+fn flatten(value: &Value, prefix: String, out: &mut BTreeMap<String, String>) {
+    match *value {
+        Value::Table(ref table) => {
+            for (key, value) in table {
+                let key = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(value, key, out);
+            }
+        }
+        _ => {
+            out.insert(prefix, value.to_string());
+        }
+    }
+}
+
+/// Maps a colour name from the config to a 256-colour palette index.
+/// `default` and unknown names give `None`.
+pub fn color_index(name: &str) -> Option<u8> {
+    let index = match name {
+        "black" => 0,
+        "red" => 1,
+        "green" => 2,
+        "yellow" => 3,
+        "blue" => 4,
+        "magenta" => 5,
+        "cyan" => 6,
+        "white" => 7,
+        "light_black" | "grey" | "gray" => 8,
+        "light_red" => 9,
+        "light_green" => 10,
+        "light_yellow" => 11,
+        "light_blue" => 12,
+        "light_magenta" => 13,
+        "light_cyan" => 14,
+        "light_white" => 15,
+        _ => return name.parse().ok(),
+    };
+    Some(index)
+}
+
+fn config_home() -> PathBuf {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => env::home_dir().unwrap_or_else(PathBuf::new).join(".config"),
+    }
+}
+
+fn find_config() -> Option<PathBuf> {
+    let mut dirs = vec![config_home()];
+    let config_dirs = env::var("XDG_CONFIG_DIRS").unwrap_or_else(|_| String::from("/etc/xdg"));
+    dirs.extend(config_dirs.split(':').filter(|x| !x.is_empty()).map(PathBuf::from));
+    dirs.into_iter()
+        .map(|dir| dir.join(APP_NAME).join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// Pulls the dotted key out of a toml error message, which ends in
+/// "for key `a.b.c`".
+fn error_key(message: &str) -> Option<String> {
+    let start = match message.rfind("for key `") {
+        Some(i) => i + "for key `".len(),
+        None => return None,
+    };
+    message[start..].find('`').map(|end| String::from(&message[start..start + end]))
+}
+
+/// Finds the 1-based line where a dotted key is defined, either as a table
+/// header or as a key inside its parent table. Tables in an array of tables
+/// are numbered from 0, as in `ui.statusbar.lines.1.left`.
+fn find_key(source: &str, key: &str) -> Option<usize> {
+    let (table, last) = match key.rfind('.') {
+        Some(i) => (&key[..i], &key[i + 1..]),
+        None => ("", key),
+    };
+    let mut current_table = String::new();
+    let mut array_lengths = BTreeMap::new();
+    for (i, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            let name = line.trim_matches(&['[', ']'][..]).trim();
+            current_table = if line.starts_with("[[") {
+                let length = array_lengths.entry(String::from(name)).or_insert(0);
+                *length += 1;
+                format!("{}.{}", name, *length - 1)
+            } else {
+                String::from(name)
+            };
+            if name == key || current_table == key {
+                return Some(i + 1);
+            }
+        } else if current_table == table {
+            let name = line.split('=').next().unwrap_or("").trim().trim_matches('"');
+            if name == last && line.contains('=') {
+                return Some(i + 1);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use toml;
+
+    use super::{ClientConfig, error_key, find_key};
+
+    const SOURCE: &'static str = "\
+network = \"libera\"
+
+[networks.libera]
+server = \"irc.libera.chat\"
+\"identity\" = \"me\"
+
+[ui.nick_colors]
+palette = [\"red\",
+           \"nope\"]
+
+[[ui.statusbar.lines]]
+left = \"{window}\"
+
+[[ui.statusbar.lines]]
+left = \"{bogus}\"
+";
+
+    #[test]
+    fn finds_keys() {
+        assert_eq!(find_key(SOURCE, "network"), Some(1));
+        assert_eq!(find_key(SOURCE, "networks.libera"), Some(3));
+        assert_eq!(find_key(SOURCE, "networks.libera.server"), Some(4));
+        assert_eq!(find_key(SOURCE, "networks.libera.identity"), Some(5));
+        assert_eq!(find_key(SOURCE, "networks.libera.port"), None);
+        assert_eq!(find_key(SOURCE, "server"), None);
+    }
+
+    #[test]
+    fn finds_keys_in_arrays_of_tables() {
+        assert_eq!(find_key(SOURCE, "ui.statusbar.lines"), Some(11));
+        assert_eq!(find_key(SOURCE, "ui.statusbar.lines.1"), Some(14));
+        assert_eq!(find_key(SOURCE, "ui.statusbar.lines.0.left"), Some(12));
+        assert_eq!(find_key(SOURCE, "ui.statusbar.lines.1.left"), Some(15));
+        assert_eq!(find_key(SOURCE, "ui.statusbar.lines.2.left"), None);
+    }
+
+    #[test]
+    fn reads_keys_from_errors() {
+        let message = "unknown field `colour`, expected one of `enabled`, `width` for key `ui.sidebar`";
+        assert_eq!(error_key(message), Some(String::from("ui.sidebar")));
+        assert_eq!(error_key("expected a table"), None);
+    }
+
+    fn validate(source: &str) -> Result<(), (Option<usize>, String)> {
+        let config: ClientConfig = toml::from_str(source).unwrap();
+        config.validate(source)
+    }
+
+    #[test]
+    fn reports_the_line_of_a_bad_template() {
+        let source = "[[ui.statusbar.lines]]\nleft = \"{window}\"\n\n[[ui.statusbar.lines]]\nright = \"{bogus}\"\n";
+        assert_eq!(validate(source).unwrap_err().0, Some(5));
+    }
+
+    #[test]
+    fn reports_the_line_of_a_bad_colour() {
+        let source = "[ui.nick_colors]\n# red\npalette = [\"red\", \"nope\"]\nbackground = \"red\"\n";
+        assert_eq!(validate(source).unwrap_err().0, Some(3));
+        let source = "[ui.nick_colors]\npalette = [\"red\"]\nbackground = \"nope\"\n";
+        assert_eq!(validate(source).unwrap_err().0, Some(3));
+    }
+}
@@ -1,32 +1,56 @@
 use chrono::{DateTime, Local};
 use irc_lib::client::data::Message;
-use termion::event::Key;
+use termion::event::{Key, MouseEvent};
 
 use std::sync::mpsc::{Sender, Receiver};
 use std::io;
+use std::time::Duration;
 
 pub type EventSender = Sender<io::Result<Event>>;
 pub type EventReceiver = Receiver<io::Result<Event>>;
 
 pub enum Event {
     Input(Key),
+    /// Only reported while mouse support is on.
+    Mouse(MouseEvent),
     Chat(ChatEvent),
+    Batch(BatchEvent),
+    /// The IRCv3 capabilities currently enabled.
+    Capabilities(Vec<String>),
+    /// How long the server took to answer a PING, or has gone without
+    /// answering one so far.
+    Lag(Duration),
+    /// The connection was lost, or the server stopped answering PINGs, for
+    /// this reason. It's being made again.
+    Reconnecting(String),
+    Tick,
+    /// Re-read the config, on SIGHUP.
+    Reload,
+    /// The terminal was resized, on SIGWINCH.
+    Resize,
 }
 
 pub struct ChatEvent {
     pub about_self: bool,
     pub is_query: bool,
     pub message: Message,
+    pub tags: Tags,
     pub timestamp: DateTime<Local>,
 }
 
 impl ChatEvent {
-    pub fn new(message: Message, about_self: bool, is_query: bool) -> ChatEvent {
+    pub fn new(mut message: Message, about_self: bool, is_query: bool) -> ChatEvent {
+        let tags = Tags::from_message(&mut message);
+        let timestamp = tags.get("time")
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+            .map(|time| time.with_timezone(&Local))
+            .unwrap_or_else(Local::now);
         ChatEvent {
             about_self: about_self,
             is_query: is_query,
             message: message,
-            timestamp: Local::now(),
+            tags: tags,
+            timestamp: timestamp,
         }
     }
 
@@ -34,3 +58,30 @@ impl ChatEvent {
         self.message.source_nickname()
     }
 }
+
+/// IRCv3 message tags, taken off the message so that they don't show up when
+/// it is displayed raw.
+pub struct Tags(Vec<(String, Option<String>)>);
+
+impl Tags {
+    fn from_message(message: &mut Message) -> Tags {
+        let tags = message.tags.take().unwrap_or_else(Vec::new);
+        Tags(tags.into_iter().map(|tag| (tag.0, tag.1)).collect())
+    }
+
+    /// Returns the value of the tag, or an empty string if the tag is present
+    /// without a value.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.iter()
+            .find(|&&(ref name, _)| name == key)
+            .map(|&(_, ref value)| value.as_ref().map(|x| &x[..]).unwrap_or(""))
+    }
+}
+
+/// A group of messages sent between `BATCH +ref` and `BATCH -ref`.
+pub struct BatchEvent {
+    pub kind: String,
+    pub label: Option<String>,
+    pub params: Vec<String>,
+    pub events: Vec<ChatEvent>,
+}
@@ -1,13 +1,24 @@
 use std::thread::{self, JoinHandle};
 use std::io;
+
+use termion::event::{Event as TermEvent, Key};
 use event::{Event, EventSender};
 
 pub fn start(event_tx: EventSender) -> JoinHandle<()> {
     thread::spawn(
         move || {
             use termion::input::TermRead;
-            for event in io::stdin().keys() {
-                let event = event.map(Event::Input);
+            for event in io::stdin().events() {
+                let event = match event {
+                    Ok(TermEvent::Key(key)) => Ok(Event::Input(key)),
+                    Ok(TermEvent::UnknownCsi(sequence)) => match unknown_csi_key(&sequence) {
+                        Some(key) => Ok(Event::Input(key)),
+                        None => continue,
+                    },
+                    Ok(TermEvent::Mouse(mouse)) => Ok(Event::Mouse(mouse)),
+                    Ok(TermEvent::Unsupported) => continue,
+                    Err(error) => Err(error),
+                };
                 if event_tx.send(event).is_err() {
                     break;
                 }
@@ -15,3 +26,14 @@ pub fn start(event_tx: EventSender) -> JoinHandle<()> {
         }
     )
 }
+
+/// termion has no keys for Ctrl with the arrows, so they're reported as the
+/// Alt keys that do the same in emacs. The sequences come without their
+/// leading `ESC [`.
+fn unknown_csi_key(sequence: &[u8]) -> Option<Key> {
+    match sequence {
+        b"1;5D" | b"5D" => Some(Key::Alt('b')),
+        b"1;5C" | b"5C" => Some(Key::Alt('f')),
+        _ => None,
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+use event::{BatchEvent, ChatEvent};
+
+struct OpenBatch {
+    parent: Option<String>,
+    batch: BatchEvent,
+}
+
+/// Collects messages tagged with `batch=ref` until the batch is closed.
+pub struct Batches {
+    open: HashMap<String, OpenBatch>,
+}
+
+impl Batches {
+    pub fn new() -> Batches {
+        Batches {
+            open: HashMap::new(),
+        }
+    }
+
+    pub fn start(&mut self, reference: &str, parent: Option<&str>, batch: BatchEvent) {
+        let parent = parent.and_then(|x| if self.open.contains_key(x) { Some(String::from(x)) } else { None });
+        self.open.insert(String::from(reference), OpenBatch { parent: parent, batch: batch });
+    }
+
+    /// Returns the event back if it doesn't belong to an open batch.
+    pub fn add(&mut self, event: ChatEvent) -> Option<ChatEvent> {
+        let reference = match event.tags.get("batch") {
+            Some(reference) => String::from(reference),
+            None => return Some(event),
+        };
+        match self.open.get_mut(&reference) {
+            Some(open) => {
+                open.batch.events.push(event);
+                None
+            }
+            None => Some(event),
+        }
+    }
+
+    /// Closes the batch. Nested batches are folded into their parent, so only
+    /// outermost batches are returned.
+    pub fn end(&mut self, reference: &str) -> Option<BatchEvent> {
+        let OpenBatch { parent, batch } = match self.open.remove(reference) {
+            Some(open) => open,
+            None => return None,
+        };
+        let parent = match parent {
+            Some(parent) => self.open.get_mut(&parent),
+            None => None,
+        };
+        match parent {
+            Some(parent) => {
+                parent.batch.events.extend(batch.events);
+                None
+            }
+            None => Some(batch),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use irc_lib::client::data::Message;
+
+    use event::{BatchEvent, ChatEvent};
+
+    use super::Batches;
+
+    fn batch(kind: &str) -> BatchEvent {
+        BatchEvent { kind: String::from(kind), label: None, params: Vec::new(), events: Vec::new() }
+    }
+
+    fn event(line: &str) -> ChatEvent {
+        ChatEvent::new(line.parse::<Message>().unwrap(), false, false)
+    }
+
+    #[test]
+    fn passes_on_events_outside_batches() {
+        let mut batches = Batches::new();
+        batches.start("a", None, batch("chathistory"));
+        assert!(batches.add(event(":nick!u@h PRIVMSG #rust :hi\r\n")).is_some());
+        assert!(batches.add(event("@batch=b :nick!u@h PRIVMSG #rust :hi\r\n")).is_some());
+    }
+
+    #[test]
+    fn collects_events_until_the_end() {
+        let mut batches = Batches::new();
+        batches.start("a", None, batch("chathistory"));
+        assert!(batches.add(event("@batch=a :nick!u@h PRIVMSG #rust :one\r\n")).is_none());
+        assert!(batches.add(event("@batch=a :nick!u@h PRIVMSG #rust :two\r\n")).is_none());
+        let done = batches.end("a").unwrap();
+        assert_eq!(done.kind, "chathistory");
+        assert_eq!(done.events.len(), 2);
+        assert!(batches.end("a").is_none());
+        assert!(batches.add(event("@batch=a :nick!u@h PRIVMSG #rust :late\r\n")).is_some());
+    }
+
+    #[test]
+    fn folds_nested_batches_into_their_parent() {
+        let mut batches = Batches::new();
+        batches.start("outer", None, batch("labeled-response"));
+        batches.start("inner", Some("outer"), batch("chathistory"));
+        batches.add(event("@batch=outer :server 311 me nick u h * :Real Name\r\n"));
+        batches.add(event("@batch=inner :nick!u@h PRIVMSG #rust :hi\r\n"));
+        assert!(batches.end("inner").is_none());
+        let done = batches.end("outer").unwrap();
+        assert_eq!(done.kind, "labeled-response");
+        assert_eq!(done.events.len(), 2);
+    }
+
+    #[test]
+    fn unknown_parents_make_outermost_batches() {
+        let mut batches = Batches::new();
+        batches.start("inner", Some("gone"), batch("netsplit"));
+        assert!(batches.end("inner").is_some());
+    }
+}
@@ -1,3 +1,7 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{SendError, Sender};
+
 use irc_lib::client::data::Message;
 
 pub enum Command {
@@ -15,5 +19,70 @@ pub enum Command {
     Quit {
         message: Option<String>,
     },
+    /// Marks us as away, or back if there's no message.
+    Away {
+        message: Option<String>,
+    },
+    /// Replies are tagged with `label`, which is only given if the server
+    /// supports labeled-response.
+    Whois {
+        nick: String,
+        label: Option<String>,
+    },
+    /// A message typed in by hand with /quote.
+    Raw {
+        message: Message,
+        label: Option<String>,
+    },
+    /// A `+typing` notification. Dropped if the server doesn't support
+    /// message-tags.
+    Typing {
+        target: String,
+        state: &'static str,
+    },
+    /// Requests history before the given msgid, or the latest history.
+    History {
+        target: String,
+        before: Option<String>,
+    },
     MessageReceived(Message),
+    /// Sent every second from within the IRC layer, to send PINGs and notice
+    /// when the server stops answering them.
+    CheckLag,
+    /// Sent from within the IRC layer when reading from the server fails, or
+    /// the server closes the connection. Once reconnected, or not, `done`
+    /// is told which.
+    ConnectionLost {
+        reason: String,
+        done: Sender<bool>,
+    },
+    /// Sent from within the IRC layer when reconnecting has finished, and
+    /// whether it worked.
+    Reconnected(bool),
+}
+
+/// Sends commands to the IRC thread, counting those it hasn't got to yet.
+#[derive(Clone)]
+pub struct CommandSender {
+    tx: Sender<Command>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl CommandSender {
+    pub fn new(tx: Sender<Command>, queued: Arc<AtomicUsize>) -> CommandSender {
+        CommandSender {
+            tx: tx,
+            queued: queued,
+        }
+    }
+
+    pub fn send(&self, command: Command) -> Result<(), SendError<Command>> {
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        self.tx.send(command)
+    }
+
+    /// How many commands are waiting to be sent to the server.
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
 }
@@ -0,0 +1,337 @@
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{IpAddr, Shutdown, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use encoding::{DecoderTrap, EncoderTrap, EncodingRef};
+use encoding::label::encoding_from_whatwg_label;
+use irc_lib::client::conn::Connection;
+use openssl::nid::Nid;
+use openssl::ssl::{Ssl, SslContext, SslMethod, SslStream, SSL_OP_NO_SSLV2, SSL_OP_NO_SSLV3, SSL_VERIFY_PEER};
+use openssl::ssl::error::SslError;
+use openssl::x509::{X509, X509StoreContext};
+
+enum Stream {
+    Plain(TcpStream),
+    Tls(SslStream<TcpStream>),
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Plain(ref mut stream) => stream.read(buf),
+            Stream::Tls(ref mut stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Plain(ref mut stream) => stream.write(buf),
+            Stream::Tls(ref mut stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Stream::Plain(ref mut stream) => stream.flush(),
+            Stream::Tls(ref mut stream) => stream.flush(),
+        }
+    }
+}
+
+/// A connection to the server like the irc crate's, except that reconnecting
+/// shuts the old socket down first. The irc crate waits for the read in
+/// progress to finish, which on a stalled connection lasts until the system
+/// gives up on it, often hours later. Reads carry on with the new socket.
+///
+/// The irc crate also reconnects by itself when reading or writing fails, but
+/// without asking for capabilities again. So errors are kept for the client
+/// to reconnect with instead: reads end as if the server had closed the
+/// connection, and writes are dropped.
+pub struct ServerConnection {
+    host: String,
+    port: u16,
+    tls: bool,
+    reader: Mutex<BufReader<Stream>>,
+    writer: Mutex<BufWriter<Stream>>,
+    /// The socket under the reader and writer, to shut it down with.
+    socket: Mutex<TcpStream>,
+    /// Goes up each time the connection is replaced.
+    generation: AtomicUsize,
+    /// Set while the old socket is shut down and the new one not yet in
+    /// its place.
+    replacing: AtomicBool,
+    /// The last error reading or writing, until it's taken.
+    failure: Arc<Mutex<Option<String>>>,
+}
+
+impl ServerConnection {
+    pub fn connect(host: &str, port: u16, tls: bool) -> io::Result<ServerConnection> {
+        let (reader, writer, socket) = try!(open(host, port, tls));
+        Ok(ServerConnection {
+            host: String::from(host),
+            port: port,
+            tls: tls,
+            reader: Mutex::new(reader),
+            writer: Mutex::new(writer),
+            socket: Mutex::new(socket),
+            generation: AtomicUsize::new(0),
+            replacing: AtomicBool::new(false),
+            failure: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Where the last error reading or writing is kept. The irc crate keeps
+    /// the connection itself, so this is shared.
+    pub fn failure(&self) -> Arc<Mutex<Option<String>>> {
+        self.failure.clone()
+    }
+
+    fn fail(&self, error: io::Error) {
+        *self.failure.lock().unwrap() = Some(error.to_string());
+    }
+}
+
+fn open(host: &str, port: u16, tls: bool) -> io::Result<(BufReader<Stream>, BufWriter<Stream>, TcpStream)> {
+    let socket = try!(TcpStream::connect((host, port)));
+    let handle = try!(socket.try_clone());
+    let (reader, writer) = if tls {
+        let stream = try!(connect_tls(host, socket).map_err(tls_error));
+        (Stream::Tls(try!(stream.try_clone())), Stream::Tls(stream))
+    } else {
+        (Stream::Plain(try!(socket.try_clone())), Stream::Plain(socket))
+    };
+    Ok((BufReader::new(reader), BufWriter::new(writer), handle))
+}
+
+/// Starts TLS on the socket, with whichever version both sides support best,
+/// and checks the server's certificate against the system's CAs and the host
+/// name.
+fn connect_tls(host: &str, socket: TcpStream) -> Result<SslStream<TcpStream>, SslError> {
+    let mut context = try!(SslContext::new(SslMethod::Sslv23));
+    context.set_options(SSL_OP_NO_SSLV2 | SSL_OP_NO_SSLV3);
+    try!(context.set_default_verify_paths());
+    let mut ssl = try!(Ssl::new(&context));
+    // Server name indication is only for names, not addresses.
+    if host.parse::<IpAddr>().is_err() {
+        try!(ssl.set_hostname(host));
+    }
+    let host = String::from(host);
+    ssl.set_verify_callback(SSL_VERIFY_PEER, move |preverified, certs| verify(preverified, certs, &host));
+    SslStream::connect(ssl, socket)
+}
+
+fn verify(preverified: bool, certs: &X509StoreContext, host: &str) -> bool {
+    // OpenSSL has checked the chain by then. Only the server's own
+    // certificate, at depth 0, says which host it's for.
+    if !preverified || certs.error_depth() != 0 {
+        return preverified;
+    }
+    match certs.get_current_cert() {
+        Some(cert) => certificate_matches(&cert, host),
+        None => false,
+    }
+}
+
+fn certificate_matches(cert: &X509, host: &str) -> bool {
+    let ip = host.parse::<IpAddr>().ok();
+    if let Some(names) = cert.subject_alt_names() {
+        return names.iter().any(|name| match ip {
+            Some(ip) => name.ipaddress().map_or(false, |address| ip_matches(address, ip)),
+            None => name.dnsname().map_or(false, |pattern| host_matches(pattern, host)),
+        });
+    }
+    // The common name only counts when there are no alternative names.
+    ip.is_none() && cert.subject_name().text_by_nid(Nid::CN).map_or(false, |name| host_matches(&name, host))
+}
+
+fn ip_matches(address: &[u8], ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => address == &ip.octets()[..],
+        IpAddr::V6(ip) => address == &ip.octets()[..],
+    }
+}
+
+/// Matches a name from a certificate against the host, ignoring case. A `*`
+/// can only be the whole leftmost label, and stands for exactly one label.
+fn host_matches(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.trim_right_matches('.').to_lowercase();
+    let host = host.trim_right_matches('.').to_lowercase();
+    if !pattern.starts_with("*.") {
+        return pattern == host;
+    }
+    let suffix = &pattern[1..];
+    // `*.com` would match far too much.
+    if !suffix[1..].contains('.') {
+        return false;
+    }
+    match host.find('.') {
+        Some(i) => i > 0 && &host[i..] == suffix,
+        None => false,
+    }
+}
+
+fn tls_error(error: SslError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("TLS error: {}", error))
+}
+
+/// The irc crate goes by this to tell the end of the connection from other
+/// errors, which it reconnects on.
+fn eof() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "EOF")
+}
+
+fn find_encoding(label: &str) -> io::Result<EncodingRef> {
+    encoding_from_whatwg_label(label)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("unknown encoding {}", label)))
+}
+
+impl Connection for ServerConnection {
+    fn send(&self, msg: &str, encoding: &str) -> io::Result<()> {
+        let encoding = try!(find_encoding(encoding));
+        let data = try!(encoding.encode(msg, EncoderTrap::Replace).map_err(|error| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("can't encode {} as {}", error, encoding.name()))
+        }));
+        let mut writer = self.writer.lock().unwrap();
+        if let Err(error) = writer.write_all(&data).and_then(|_| writer.flush()) {
+            self.fail(error);
+        }
+        Ok(())
+    }
+
+    fn recv(&self, encoding: &str) -> io::Result<String> {
+        let encoding = try!(find_encoding(encoding));
+        loop {
+            let generation = self.generation.load(Ordering::SeqCst);
+            let mut line = Vec::new();
+            let result = self.reader.lock().unwrap().read_until(b'\n', &mut line);
+            let replaced = self.replacing.load(Ordering::SeqCst)
+                || self.generation.load(Ordering::SeqCst) != generation;
+            match result {
+                Ok(_) if line.ends_with(b"\n") => {}
+                // Whatever came from the old socket after it was shut down
+                // is dropped, and the read starts over on the new one.
+                _ if replaced => {
+                    thread::sleep(Duration::from_millis(10));
+                    continue;
+                }
+                Ok(_) if line.is_empty() => return Err(eof()),
+                Ok(_) => {}
+                Err(error) => {
+                    self.fail(error);
+                    return Err(eof());
+                }
+            }
+            return encoding.decode(&line, DecoderTrap::Replace).map_err(|error| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("can't decode {} as {}", error, encoding.name()))
+            });
+        }
+    }
+
+    fn written(&self, _: &str) -> Option<String> {
+        None
+    }
+
+    fn reconnect(&self) -> io::Result<()> {
+        let (reader, writer, socket) = try!(open(&self.host, self.port, self.tls));
+        self.replacing.store(true, Ordering::SeqCst);
+        // This wakes up a read or write blocked on the old socket, which lets
+        // go of the reader or writer.
+        let _ = self.socket.lock().unwrap().shutdown(Shutdown::Both);
+        *self.reader.lock().unwrap() = reader;
+        *self.writer.lock().unwrap() = writer;
+        *self.socket.lock().unwrap() = socket;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.replacing.store(false, Ordering::SeqCst);
+        // Errors from the old socket are done with.
+        *self.failure.lock().unwrap() = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use irc_lib::client::conn::Connection;
+
+    use super::{ServerConnection, host_matches, ip_matches};
+
+    #[test]
+    fn reconnecting_wakes_up_a_stalled_read() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connection = Arc::new(ServerConnection::connect("127.0.0.1", port, false).unwrap());
+        // The first connection is kept open but never sends anything.
+        let (_stalled, _) = listener.accept().unwrap();
+        let reader = {
+            let connection = connection.clone();
+            thread::spawn(move || connection.recv("UTF-8"))
+        };
+        thread::sleep(Duration::from_millis(50));
+        connection.reconnect().unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        server.write_all(b"PING :hello\r\n").unwrap();
+        assert_eq!(reader.join().unwrap().unwrap(), "PING :hello\r\n");
+    }
+
+    #[test]
+    fn closed_connection_ends_with_eof() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connection = ServerConnection::connect("127.0.0.1", port, false).unwrap();
+        drop(listener.accept().unwrap());
+        let error = connection.recv("UTF-8").unwrap_err();
+        assert_eq!(error.to_string(), "EOF");
+    }
+
+    #[test]
+    fn failed_writes_are_kept_for_later() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connection = ServerConnection::connect("127.0.0.1", port, false).unwrap();
+        drop(listener.accept().unwrap());
+        let failure = connection.failure();
+        // The first write after the other end closes can still succeed.
+        for _ in 0..10 {
+            assert!(connection.send("PING :hello\r\n", "UTF-8").is_ok());
+            if failure.lock().unwrap().is_some() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("the write never failed");
+    }
+
+    #[test]
+    fn matches_host_names() {
+        assert!(host_matches("irc.libera.chat", "irc.libera.chat"));
+        assert!(host_matches("IRC.Libera.Chat", "irc.libera.chat."));
+        assert!(!host_matches("irc.libera.chat", "irc.libera.chat.evil.com"));
+        assert!(!host_matches("libera.chat", "irc.libera.chat"));
+    }
+
+    #[test]
+    fn matches_wildcards_for_one_label() {
+        assert!(host_matches("*.libera.chat", "irc.libera.chat"));
+        assert!(!host_matches("*.libera.chat", "libera.chat"));
+        assert!(!host_matches("*.libera.chat", "a.irc.libera.chat"));
+        assert!(!host_matches("*.chat", "libera.chat"));
+        assert!(!host_matches("irc.*.chat", "irc.libera.chat"));
+    }
+
+    #[test]
+    fn matches_addresses() {
+        assert!(ip_matches(&[127, 0, 0, 1], "127.0.0.1".parse().unwrap()));
+        assert!(!ip_matches(&[127, 0, 0, 2], "127.0.0.1".parse().unwrap()));
+        assert!(!ip_matches(&[127, 0, 0, 1], "::1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,115 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How often the lag is measured. These are the only PINGs sent, as the irc
+/// crate's own are turned off.
+const LAG_INTERVAL: u64 = 30;
+/// Our PINGs carry this followed by when they were sent, in milliseconds
+/// since the epoch, to tell their PONGs from any others.
+const TOKEN_PREFIX: &'static str = "simpleirc-";
+
+/// Measures lag with one PING at a time, timing how long its PONG takes.
+pub struct Lag {
+    /// The token of the PING waiting for its PONG, and when it was sent.
+    pending: Option<(String, Instant)>,
+    last_sent: Option<Instant>,
+}
+
+impl Lag {
+    pub fn new() -> Lag {
+        Lag {
+            pending: None,
+            last_sent: None,
+        }
+    }
+
+    /// The token to send a PING with, if it's time for one.
+    pub fn ping(&mut self) -> Option<String> {
+        if self.pending.is_some() {
+            return None;
+        }
+        if let Some(last_sent) = self.last_sent {
+            if last_sent.elapsed() < Duration::from_secs(LAG_INTERVAL) {
+                return None;
+            }
+        }
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0));
+        let millis = since_epoch.as_secs() * 1000 + (since_epoch.subsec_nanos() / 1_000_000) as u64;
+        let token = format!("{}{}", TOKEN_PREFIX, millis);
+        let now = Instant::now();
+        self.pending = Some((token.clone(), now));
+        self.last_sent = Some(now);
+        Some(token)
+    }
+
+    /// How long the last PING has gone without a PONG.
+    pub fn waiting(&self) -> Option<Duration> {
+        self.pending.as_ref().map(|&(_, sent)| sent.elapsed())
+    }
+
+    /// Whether the last PING has gone without a PONG for longer than
+    /// `timeout`.
+    pub fn timed_out(&self, timeout: Duration) -> bool {
+        self.waiting().map_or(false, |waiting| waiting > timeout)
+    }
+
+    /// Returns the lag if the PONG answers our PING.
+    pub fn pong(&mut self, token: &str) -> Option<Duration> {
+        let lag = match self.pending {
+            Some((ref pending, sent)) if pending == token => sent.elapsed(),
+            _ => return None,
+        };
+        self.pending = None;
+        Some(lag)
+    }
+
+    /// Forgets the PING in flight, for a new connection.
+    pub fn reset(&mut self) {
+        self.pending = None;
+        self.last_sent = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{Lag, TOKEN_PREFIX};
+
+    #[test]
+    fn pong_answers_its_ping() {
+        let mut lag = Lag::new();
+        let token = lag.ping().unwrap();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        // One PING at a time.
+        assert_eq!(lag.ping(), None);
+        assert_eq!(lag.pong("irc.example.org"), None);
+        assert!(lag.waiting().is_some());
+        assert!(lag.pong(&token).is_some());
+        assert_eq!(lag.waiting(), None);
+        // A second PONG for it doesn't count.
+        assert_eq!(lag.pong(&token), None);
+    }
+
+    #[test]
+    fn pings_wait_for_the_interval() {
+        let mut lag = Lag::new();
+        let token = lag.ping().unwrap();
+        lag.pong(&token);
+        assert_eq!(lag.ping(), None);
+        lag.reset();
+        assert!(lag.ping().is_some());
+    }
+
+    #[test]
+    fn times_out_after_the_timeout() {
+        let mut lag = Lag::new();
+        assert!(!lag.timed_out(Duration::from_secs(0)));
+        lag.ping().unwrap();
+        assert!(!lag.timed_out(Duration::from_secs(60)));
+        thread::sleep(Duration::from_millis(20));
+        assert!(lag.timed_out(Duration::from_millis(10)));
+        lag.reset();
+        assert!(!lag.timed_out(Duration::from_secs(0)));
+    }
+}
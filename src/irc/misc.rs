@@ -20,3 +20,86 @@ fn to_irc_lower(c: u8) -> u8 {
     }
 }
 
+pub fn irc_lower(s: &str) -> String {
+    let bytes = s.bytes().map(to_irc_lower).collect();
+    String::from_utf8(bytes).unwrap_or_else(|_| String::from(s))
+}
+
+pub fn is_channel(name: &str) -> bool {
+    name.starts_with(&['#', '&', '+', '!'][..])
+}
+
+/// The longest `user@host` we assume the server adds to our messages when
+/// relaying them, as we can't know ours.
+const USER_HOST_LEN: usize = 10 + 1 + 63;
+
+/// How many bytes of text fit in a PRIVMSG to `target`, once the server has
+/// put our prefix in front of it.
+pub fn message_limit(nick: &str, target: &str) -> usize {
+    // ":nick!user@host PRIVMSG target :text\r\n"
+    let overhead = 1 + nick.len() + 1 + USER_HOST_LEN + 1 + "PRIVMSG ".len() + target.len() + " :".len() + 2;
+    512usize.saturating_sub(overhead)
+}
+
+/// Matches a nick or a `nick!user@host` mask against a message prefix. `*`
+/// matches any run of characters and `?` any single one.
+pub fn mask_matches(mask: &str, prefix: &str) -> bool {
+    let mask = if mask.contains('!') || mask.contains('@') {
+        irc_lower(mask)
+    } else {
+        format!("{}!*@*", irc_lower(mask))
+    };
+    let prefix = irc_lower(prefix);
+    // A server prefix has no user or host, but nicks always do.
+    let prefix = if prefix.contains('!') { prefix } else { format!("{}!@", prefix) };
+    wildcard_match(mask.as_bytes(), prefix.as_bytes())
+}
+
+fn wildcard_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((&b'*', rest)) => (0..text.len() + 1).any(|i| wildcard_match(rest, &text[i..])),
+        Some((&b'?', rest)) => !text.is_empty() && wildcard_match(rest, &text[1..]),
+        Some((&c, rest)) => text.first() == Some(&c) && wildcard_match(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mask_matches, wildcard_match};
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(wildcard_match(b"abc", b"abc"));
+        assert!(!wildcard_match(b"abc", b"abcd"));
+        assert!(wildcard_match(b"a*", b"a"));
+        assert!(wildcard_match(b"a*c", b"abbbc"));
+        assert!(!wildcard_match(b"a*c", b"abbb"));
+        assert!(wildcard_match(b"a?c", b"abc"));
+        assert!(!wildcard_match(b"a?c", b"ac"));
+        assert!(wildcard_match(b"*", b""));
+        assert!(!wildcard_match(b"", b"a"));
+    }
+
+    #[test]
+    fn nicks_match_any_user_and_host() {
+        assert!(mask_matches("spammer", "spammer!bot@example.com"));
+        assert!(mask_matches("spam*", "Spammer2!bot@example.com"));
+        assert!(!mask_matches("spammer", "spammer2!bot@example.com"));
+    }
+
+    #[test]
+    fn masks_match_whole_prefixes() {
+        assert!(mask_matches("*!*@example.com", "anyone!user@example.com"));
+        assert!(!mask_matches("*!*@example.com", "anyone!user@example.org"));
+        assert!(mask_matches("*!bot@*", "someone!bot@host"));
+        // Case is compared the way IRC does, with [] as the upper case of {}.
+        assert!(mask_matches("NICK[M]", "nick{m}!u@h"));
+    }
+
+    #[test]
+    fn server_prefixes_have_no_user_or_host() {
+        assert!(mask_matches("irc.example.com", "irc.example.com"));
+        assert!(!mask_matches("*!*@irc.example.com", "irc.example.com"));
+    }
+}
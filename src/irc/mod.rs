@@ -1,13 +1,80 @@
 use std::thread;
-use std::sync::mpsc::{Sender, channel};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
 use std::error::Error;
+use std::io;
+use std::time::Duration;
 
 use irc_lib::client::prelude::*;
+use irc_lib::client::data::command::{BatchSubCommand, CapSubCommand};
+use irc_lib::client::data::message::Tag;
 
-use event::{Event, ChatEvent, EventSender};
+use event::{Event, ChatEvent, BatchEvent, EventSender};
+use self::misc::is_channel;
 
 pub mod command;
 pub mod misc;
+mod batch;
+mod connection;
+mod lag;
+mod secret;
+
+/// IRCv3 capabilities requested before registration.
+const CAPABILITIES: &'static [&'static str] = &[
+    "server-time",
+    "message-tags",
+    "batch",
+    "echo-message",
+    "labeled-response",
+    "account-tag",
+    "account-notify",
+    "draft/chathistory",
+    "away-notify",
+];
+
+/// How many messages to request at a time with CHATHISTORY.
+const HISTORY_LIMIT: &'static str = "50";
+/// How long to wait before trying again when reconnecting fails.
+const RECONNECT_DELAY: u64 = 10;
+
+fn get_tag<'a>(message: &'a Message, key: &str) -> Option<&'a str> {
+    message.tags.as_ref()
+        .and_then(|tags| tags.iter().find(|tag| tag.0 == key))
+        .map(|tag| tag.1.as_ref().map(|x| &x[..]).unwrap_or(""))
+}
+
+/// Attaches the label to the command, if there is one.
+fn labeled(command: Command, label: Option<String>) -> Message {
+    Message {
+        tags: label.map(|label| vec![Tag(String::from("label"), Some(label))]),
+        prefix: None,
+        command: command,
+    }
+}
+
+/// Asks for capabilities and registers, on connecting and on reconnecting.
+fn register(server: &IrcServer) -> io::Result<()> {
+    // Each capability gets its own request, so that a server lacking one of
+    // them doesn't reject the rest.
+    for cap in CAPABILITIES {
+        try!(server.send(Command::CAP(None, CapSubCommand::REQ, None, Some(String::from(*cap)))));
+    }
+    server.identify()
+}
+
+/// Reconnects on a thread of its own, as connecting can take a while, and
+/// tells the event loop when it's done.
+fn reconnect(server: &IrcServer, irc_tx: &Sender<command::Command>) {
+    let server = server.clone();
+    let irc_tx = irc_tx.clone();
+    let _ = thread::Builder::new()
+        .name(String::from("irc_reconnect"))
+        .spawn(move || {
+            let reconnected = server.reconnect().and_then(|_| register(&server)).is_ok();
+            let _ = irc_tx.send(command::Command::Reconnected(reconnected));
+        });
+}
 
 type Handle = Option<thread::JoinHandle<()>>;
 
@@ -23,20 +90,65 @@ impl Drop for ServerHandles {
     }
 }
 
-pub fn start(event_tx: EventSender) -> Result<(ServerHandles, Sender<command::Command>), Box<Error>> {
+/// The irc crate panics on a missing server or nickname, so they're checked
+/// before connecting.
+pub fn check_config(config: &Config) -> Result<(), String> {
+    if config.server.is_none() {
+        return Err(String::from("No server configured. Use --server, or add a network to the config."));
+    }
+    if config.nickname.is_none() {
+        return Err(String::from("No nickname configured. Use --nick, or add an identity to the config."));
+    }
+    Ok(())
+}
+
+pub fn start(mut config: Config, event_tx: EventSender) -> Result<(ServerHandles, command::CommandSender), Box<Error>> {
     let (irc_tx, irc_rx) = channel();
+    let queued = Arc::new(AtomicUsize::new(0));
+
+    let mut secrets = secret::Secrets::new();
+    config.password = try!(secrets.resolve("server password", config.password.take()));
+    config.nick_password = try!(secrets.resolve("NickServ password", config.nick_password.take()));
 
-    let server = try!(IrcServer::new("config.json"));
-    try!(server.identify());
+    let ping_timeout = Duration::from_secs(config.ping_timeout() as u64);
+    // This way the irc crate never sends PINGs of its own, so it never times
+    // out on them and reconnects without asking for capabilities. The lag
+    // PINGs do that job instead.
+    config.ping_time = Some(u32::max_value());
+    let connection = try!(connection::ServerConnection::connect(config.server(), config.port(), config.use_ssl()));
+    let failure = connection.failure();
+    let server = IrcServer::from_connection(config, connection);
+    try!(register(&server));
 
     let message_receiver = {
         let server = server.clone();
         let irc_tx = irc_tx.clone();
-        move || {
+        move || loop {
             for message in server.iter() {
-                let message = message.unwrap();
+                // Lines that don't parse are left out.
+                let message = match message {
+                    Ok(message) => message,
+                    Err(_) => continue,
+                };
                 if irc_tx.send(command::Command::MessageReceived(message)).is_err() {
-                    break;
+                    return;
+                }
+            }
+            let reason = match failure.lock().unwrap().take() {
+                Some(error) => format!("Lost the connection ({})", error),
+                None => String::from("The server closed the connection"),
+            };
+            // After quitting, the event loop is gone and this ends.
+            loop {
+                let (done_tx, done_rx) = channel();
+                let lost = command::Command::ConnectionLost { reason: reason.clone(), done: done_tx };
+                if irc_tx.send(lost).is_err() {
+                    return;
+                }
+                match done_rx.recv() {
+                    Ok(true) => break,
+                    Ok(false) => thread::sleep(Duration::from_secs(RECONNECT_DELAY)),
+                    Err(_) => return,
                 }
             }
         }
@@ -46,9 +158,34 @@ pub fn start(event_tx: EventSender) -> Result<(ServerHandles, Sender<command::Co
         .name(String::from("irc_receiver"))
         .spawn(message_receiver));
 
+    {
+        let irc_tx = irc_tx.clone();
+        try!(thread::Builder::new()
+            .name(String::from("irc_lag"))
+            .spawn(move || {
+                while irc_tx.send(command::Command::CheckLag).is_ok() {
+                    thread::sleep(Duration::from_secs(1));
+                }
+            }));
+    }
+
+    let command_sender = command::CommandSender::new(irc_tx.clone(), queued.clone());
     let event_loop = move || {
+        let mut batches = batch::Batches::new();
+        let mut caps: Vec<String> = Vec::new();
+        let mut lag = lag::Lag::new();
+        let mut reconnecting = false;
+        // Told whether reconnecting worked, once it's done.
+        let mut lost: Vec<Sender<bool>> = Vec::new();
         for event in irc_rx {
             use self::command::Command::*;
+            // Commands from our own threads aren't counted.
+            match event {
+                MessageReceived(_) | CheckLag | ConnectionLost { .. } | Reconnected(_) => {}
+                _ => {
+                    queued.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
             match event {
                 Join { channel } => {
                     server.send_join(&channel).unwrap();
@@ -58,21 +195,143 @@ pub fn start(event_tx: EventSender) -> Result<(ServerHandles, Sender<command::Co
                 }
                 PrivMsg { target, message } => {
                     server.send_privmsg(&target, &message).unwrap();
+                    // Without echo-message, the server won't tell us about our
+                    // own messages, so we make one up.
+                    if !caps.iter().any(|cap| cap == "echo-message") {
+                        let message = Message {
+                            tags: None,
+                            prefix: Some(String::from(server.current_nickname())),
+                            command: Command::PRIVMSG(target.clone(), message),
+                        };
+                        let message = secrets.redact(message);
+                        let event = ChatEvent::new(message, true, !is_channel(&target));
+                        event_tx.send(Ok(Event::Chat(event))).unwrap();
+                    }
                 }
                 Quit { message } => {
                     let message = message.as_ref().map(|x| &x[..]).unwrap_or("");
                     server.send_quit(message).unwrap();
                     break;
                 }
+                Away { message } => {
+                    server.send(Command::AWAY(message)).unwrap();
+                }
+                Whois { nick, label } => {
+                    server.send(labeled(Command::WHOIS(None, nick), label)).unwrap();
+                }
+                Raw { message, label } => {
+                    server.send(labeled(message.command, label)).unwrap();
+                }
+                Typing { target, state } => {
+                    if caps.iter().any(|cap| cap == "message-tags") {
+                        let message = Message {
+                            tags: Some(vec![Tag(String::from("+typing"), Some(String::from(state)))]),
+                            prefix: None,
+                            command: Command::Raw(String::from("TAGMSG"), vec![target], None),
+                        };
+                        server.send(message).unwrap();
+                    }
+                }
+                History { target, before } => {
+                    let mut args = match before {
+                        Some(msgid) => vec![String::from("BEFORE"), target, format!("msgid={}", msgid)],
+                        None => vec![String::from("LATEST"), target, String::from("*")],
+                    };
+                    args.push(String::from(HISTORY_LIMIT));
+                    server.send(Command::Raw(String::from("CHATHISTORY"), args, None)).unwrap();
+                }
+                CheckLag => {
+                    if reconnecting {
+                        continue;
+                    }
+                    match lag.waiting() {
+                        Some(waiting) if lag.timed_out(ping_timeout) => {
+                            lag.reset();
+                            caps.clear();
+                            reconnecting = true;
+                            let reason = format!("No reply from the server in {} seconds", waiting.as_secs());
+                            event_tx.send(Ok(Event::Reconnecting(reason))).unwrap();
+                            reconnect(&server, &irc_tx);
+                        }
+                        // Until the PONG comes, the lag is at least this.
+                        Some(waiting) => {
+                            if waiting >= Duration::from_secs(1) {
+                                event_tx.send(Ok(Event::Lag(waiting))).unwrap();
+                            }
+                        }
+                        None => {
+                            if let Some(token) = lag.ping() {
+                                server.send(Command::PING(token, None)).unwrap();
+                            }
+                        }
+                    }
+                }
+                ConnectionLost { reason, done } => {
+                    lost.push(done);
+                    if !reconnecting {
+                        lag.reset();
+                        caps.clear();
+                        reconnecting = true;
+                        event_tx.send(Ok(Event::Reconnecting(reason))).unwrap();
+                        reconnect(&server, &irc_tx);
+                    }
+                }
+                Reconnected(reconnected) => {
+                    reconnecting = false;
+                    for done in lost.drain(..) {
+                        let _ = done.send(reconnected);
+                    }
+                }
                 MessageReceived(message) => {
+                    if let Command::PONG(ref origin, ref token) = message.command {
+                        let token = token.as_ref().unwrap_or(origin);
+                        if let Some(measured) = lag.pong(token) {
+                            event_tx.send(Ok(Event::Lag(measured))).unwrap();
+                            continue;
+                        }
+                    }
+                    if let Command::CAP(_, CapSubCommand::ACK, ref arg, ref suffix) = message.command {
+                        let acked = suffix.as_ref().or(arg.as_ref()).map(|x| &x[..]).unwrap_or("");
+                        for cap in acked.split_whitespace() {
+                            if cap.starts_with('-') {
+                                caps.retain(|x| x != &cap[1..]);
+                            } else {
+                                caps.push(String::from(cap));
+                            }
+                        }
+                        event_tx.send(Ok(Event::Capabilities(caps.clone()))).unwrap();
+                    }
+                    if let Command::BATCH(ref reference, ref kind, ref params) = message.command {
+                        if reference.starts_with('+') {
+                            let kind = match *kind {
+                                Some(BatchSubCommand::NETSPLIT) => "netsplit",
+                                Some(BatchSubCommand::NETJOIN) => "netjoin",
+                                Some(BatchSubCommand::CUSTOM(ref kind)) => kind,
+                                None => "",
+                            };
+                            let batch = BatchEvent {
+                                kind: kind.to_lowercase(),
+                                label: get_tag(&message, "label").map(String::from),
+                                params: params.clone().unwrap_or_else(Vec::new),
+                                events: Vec::new(),
+                            };
+                            batches.start(&reference[1..], get_tag(&message, "batch"), batch);
+                        } else if let Some(batch) = batches.end(&reference[1..]) {
+                            event_tx.send(Ok(Event::Batch(batch))).unwrap();
+                        }
+                        continue;
+                    }
+                    let message = secrets.redact(message);
                     let about_self = Some(server.current_nickname()) == message.source_nickname();
                     let is_query = match message.command {
                         Command::PRIVMSG(ref target, _) | Command::NOTICE(ref target, _)
-                            => target == server.current_nickname(),
+                            => target == server.current_nickname() || (about_self && !is_channel(target)),
                         _ => false,
                     };
-                    let event = Ok(Event::Chat(ChatEvent::new(message, about_self, is_query)));
-                    event_tx.send(event).unwrap();
+                    let event = ChatEvent::new(message, about_self, is_query);
+                    if let Some(event) = batches.add(event) {
+                        event_tx.send(Ok(Event::Chat(event))).unwrap();
+                    }
                 }
             }
         }
@@ -87,5 +346,5 @@ pub fn start(event_tx: EventSender) -> Result<(ServerHandles, Sender<command::Co
         message_receiver: Some(message_receiver),
         event_loop: Some(event_loop),
     };
-    Ok((server_handles, irc_tx))
+    Ok((server_handles, command_sender))
 }
@@ -0,0 +1,190 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::process::{self, Stdio};
+
+use irc_lib::client::data::Message;
+
+/// What secrets are replaced with before anything is displayed or logged.
+const REDACTED: &'static str = "********";
+
+/// Secrets from the config, resolved when connecting so that they never sit
+/// in the client config in plain text. A value can be given as:
+///
+/// * `env:VAR`, the value of an environment variable,
+/// * `cmd:COMMAND`, the first line a shell command prints, e.g.
+///   `cmd:pass show irc/libera`,
+/// * `file:/path`, the first line of a file only its owner can read,
+///
+/// or literally, if it has none of these prefixes.
+pub struct Secrets {
+    values: Vec<String>,
+}
+
+impl Secrets {
+    pub fn new() -> Secrets {
+        Secrets {
+            values: Vec::new(),
+        }
+    }
+
+    /// Resolves a config field, remembering the result so that it can be
+    /// redacted later. `name` is only used in error messages.
+    pub fn resolve(&mut self, name: &str, value: Option<String>) -> Result<Option<String>, String> {
+        let value = match value {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let secret = try!(resolve(&value).map_err(|error| format!("Couldn't get the {}: {}", name, error)));
+        if !secret.is_empty() {
+            self.values.push(secret.clone());
+        }
+        Ok(Some(secret))
+    }
+
+    /// Replaces any secret in the message, such as an echoed
+    /// `PRIVMSG NickServ :IDENTIFY`, so that it's never displayed or logged.
+    pub fn redact(&self, message: Message) -> Message {
+        let mut line = message.to_string();
+        if !self.values.iter().any(|secret| line.contains(&secret[..])) {
+            return message;
+        }
+        for secret in &self.values {
+            line = line.replace(&secret[..], REDACTED);
+        }
+        line.parse().unwrap_or(message)
+    }
+}
+
+fn resolve(value: &str) -> Result<String, String> {
+    if value.starts_with("env:") {
+        let var = &value["env:".len()..];
+        env::var(var).map_err(|_| format!("${} isn't set", var))
+    } else if value.starts_with("cmd:") {
+        run(&value["cmd:".len()..])
+    } else if value.starts_with("file:") {
+        read(&value["file:".len()..])
+    } else {
+        Ok(String::from(value))
+    }
+}
+
+fn run(command: &str) -> Result<String, String> {
+    let output = try!(
+        process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|error| format!("`{}` couldn't be run: {}", command, error)));
+    if !output.status.success() {
+        // The output might be the secret, so only stderr is shown.
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().next().unwrap_or("").trim();
+        return Err(format!("`{}` failed ({}) {}", command, output.status, reason));
+    }
+    let stdout = try!(String::from_utf8(output.stdout).map_err(|_| format!("`{}` printed invalid UTF-8", command)));
+    Ok(first_line(&stdout))
+}
+
+fn read(path: &str) -> Result<String, String> {
+    let metadata = try!(fs::metadata(path).map_err(|error| format!("{}: {}", path, error)));
+    try!(check_permissions(path, &metadata));
+    let mut contents = String::new();
+    try!(
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|error| format!("{}: {}", path, error)));
+    Ok(first_line(&contents))
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &str, metadata: &fs::Metadata) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+    if metadata.permissions().mode() & 0o077 != 0 {
+        return Err(format!("{} can be read by other users; `chmod 600` it first", path));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &str, _metadata: &fs::Metadata) -> Result<(), String> {
+    Ok(())
+}
+
+fn first_line(text: &str) -> String {
+    String::from(text.lines().next().unwrap_or("").trim_right_matches('\r'))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process;
+
+    use irc_lib::client::data::Message;
+
+    use super::{Secrets, resolve};
+
+    #[test]
+    fn takes_plain_values_literally() {
+        assert_eq!(resolve("hunter2"), Ok(String::from("hunter2")));
+    }
+
+    #[test]
+    fn reads_environment_variables() {
+        env::set_var("SIMPLEIRC_TEST_SECRET", "from-env");
+        assert_eq!(resolve("env:SIMPLEIRC_TEST_SECRET"), Ok(String::from("from-env")));
+        assert!(resolve("env:SIMPLEIRC_TEST_UNSET").is_err());
+    }
+
+    #[test]
+    fn runs_commands_for_their_first_line() {
+        assert_eq!(resolve("cmd:printf 'first\\nsecond\\n'"), Ok(String::from("first")));
+        // What the command printed is left out of the error, in case it's the
+        // secret.
+        let error = resolve("cmd:echo pass$((1 + 1)); echo oops >&2; exit 3").unwrap_err();
+        assert!(error.contains("oops"));
+        assert!(!error.contains("pass2"));
+    }
+
+    #[cfg(unix)]
+    fn temporary_file(name: &str, contents: &str, mode: u32) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+        let path = env::temp_dir().join(format!("simpleirc-{}-{}", process::id(), name));
+        File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        path
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn reads_private_files() {
+        let path = temporary_file("private", "from-file\r\nmore\n", 0o600);
+        let result = resolve(&format!("file:{}", path.display()));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result, Ok(String::from("from-file")));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn refuses_files_others_can_read() {
+        let path = temporary_file("public", "from-file\n", 0o644);
+        let result = resolve(&format!("file:{}", path.display()));
+        fs::remove_file(&path).unwrap();
+        assert!(result.unwrap_err().contains("chmod 600"));
+    }
+
+    #[test]
+    fn redacts_resolved_secrets() {
+        let mut secrets = Secrets::new();
+        assert_eq!(secrets.resolve("password", None), Ok(None));
+        assert_eq!(secrets.resolve("password", Some(String::from("hunter2"))), Ok(Some(String::from("hunter2"))));
+        let message: Message = "PRIVMSG NickServ :IDENTIFY hunter2\r\n".parse().unwrap();
+        assert_eq!(secrets.redact(message).to_string(), "PRIVMSG NickServ :IDENTIFY ********\r\n");
+        let error = secrets.resolve("nick password", Some(String::from("env:SIMPLEIRC_TEST_UNSET"))).unwrap_err();
+        assert!(error.starts_with("Couldn't get the nick password"));
+    }
+}
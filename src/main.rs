@@ -1,21 +1,55 @@
 extern crate irc as irc_lib;
 extern crate termion;
 extern crate chrono;
-extern crate rustyline;
 extern crate unicode_width;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
+extern crate getopts;
+extern crate chan_signal;
+extern crate encoding;
+extern crate openssl;
 
+use std::process;
 use std::sync::mpsc::channel;
 
 mod tui;
 mod irc;
 mod event;
 mod input;
+mod timer;
+mod config;
+mod cli;
+mod signal;
 use tui::Tui;
+use config::ClientConfig;
 
 fn main() {
+    let args = cli::parse();
+    let config = match ClientConfig::load(args.config.as_ref().map(|x| x.as_path())) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+    let mut irc_config = config.irc_config();
+    args.apply(&mut irc_config);
+    if !args.no_connect {
+        if let Err(error) = irc::check_config(&irc_config) {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    }
     let (event_tx, event_rx) = channel();
-    let (_irc_threads, irc_tx) = irc::start(event_tx.clone()).unwrap();
-    let _input_thread = input::start(event_tx);
-    let mut tui = Tui::new(event_rx, irc_tx).unwrap();
+    let _signal_thread = signal::start(event_tx.clone());
+    let _timer_thread = timer::start(event_tx.clone());
+    let _input_thread = input::start(event_tx.clone());
+    let connect = !args.no_connect;
+    let mut tui = Tui::new(event_rx, event_tx, args, config).unwrap();
+    if connect {
+        tui.connect();
+    }
     tui.event_loop();
 }
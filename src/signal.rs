@@ -0,0 +1,25 @@
+use std::thread::{self, JoinHandle};
+
+use chan_signal::{self, Signal};
+use event::{Event, EventSender};
+
+/// Turns signals into events. This has to be started before any other
+/// thread, so that the signals are blocked everywhere else and only
+/// delivered here.
+pub fn start(event_tx: EventSender) -> JoinHandle<()> {
+    let signals = chan_signal::notify(&[Signal::HUP, Signal::WINCH]);
+    thread::spawn(
+        move || {
+            while let Some(signal) = signals.recv() {
+                let event = match signal {
+                    Signal::HUP => Event::Reload,
+                    Signal::WINCH => Event::Resize,
+                    _ => continue,
+                };
+                if event_tx.send(Ok(event)).is_err() {
+                    break;
+                }
+            }
+        }
+    )
+}
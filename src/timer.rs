@@ -0,0 +1,18 @@
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use event::{Event, EventSender};
+
+/// Sends an `Event::Tick` every second, for anything that has to happen
+/// without user input.
+pub fn start(event_tx: EventSender) -> JoinHandle<()> {
+    thread::spawn(
+        move || {
+            loop {
+                thread::sleep(Duration::from_secs(1));
+                if event_tx.send(Ok(Event::Tick)).is_err() {
+                    break;
+                }
+            }
+        }
+    )
+}
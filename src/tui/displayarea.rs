@@ -2,96 +2,789 @@ use event::ChatEvent;
 
 use std::collections::VecDeque;
 use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant};
 
-use termion::{self, cursor, clear};
+use chrono::{DateTime, Local};
+use termion::{cursor, style};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use config::MessageFormat;
+
+use super::Settings;
+use super::layout::{Rect, SharedLayout};
+use super::log;
+use super::theme::Theme;
 
 pub struct DisplayArea {
+    name: String,
+    settings: Settings,
+    layout: SharedLayout,
     messages: Messages,
-    to_be_displayed: Cell<usize>,
+    /// How many messages from the bottom are scrolled out of view.
+    scroll: Cell<usize>,
+    /// When history was asked for, while it's loading.
+    loading: Cell<Option<Instant>>,
+    /// Set when there's something new to draw.
+    dirty: Cell<bool>,
+    /// The size it was last drawn at, for scrolling by half of it.
+    rows: Cell<u16>,
+    cols: Cell<u16>,
+    /// When the newest message that's been on screen was sent, in
+    /// milliseconds since the epoch, so that messages sent in the same second
+    /// can be told apart.
+    read_until: Cell<Option<i64>>,
+    /// Where `read_until` was when the window was last left. Messages after
+    /// it get a line above them.
+    marker: Cell<Option<i64>>,
 }
 
 impl DisplayArea {
-    pub fn new() -> DisplayArea {
+    pub fn new(name: &str, settings: Settings, layout: SharedLayout) -> DisplayArea {
         DisplayArea {
-            messages: Messages::with_max(100),
-            to_be_displayed: Cell::new(0),
+            name: String::from(name),
+            settings: settings,
+            layout: layout,
+            messages: Messages::new(),
+            scroll: Cell::new(0),
+            loading: Cell::new(None),
+            dirty: Cell::new(true),
+            rows: Cell::new(0),
+            cols: Cell::new(0),
+            read_until: Cell::new(None),
+            marker: Cell::new(None),
+        }
+    }
+
+    /// Highlighted messages are drawn in the highlight style. `prefix` is
+    /// the sender's mode prefix in the channel, if any.
+    pub fn show_event(&self, event: &ChatEvent, highlight: bool, prefix: &str) {
+        let line = Line::from_event(event, highlight, prefix, &self.settings.borrow().ui.messages);
+        self.add_line(line);
+    }
+
+    /// Inserts an event by its timestamp, rather than at the end. Events
+    /// already in the scrollback are skipped.
+    pub fn insert_event(&self, event: &ChatEvent, highlight: bool, prefix: &str) {
+        let line = Line::from_event(event, highlight, prefix, &self.settings.borrow().ui.messages);
+        if let Some(ref msgid) = line.msgid {
+            if self.messages.find_msgid(msgid) {
+                return;
+            }
+        }
+        self.messages.insert(line, self.settings.borrow().ui.scrollback);
+        // The view can't be scrolled further than the oldest message left.
+        let oldest = self.messages.len().saturating_sub(1);
+        if self.scroll.get() > oldest {
+            self.scroll.set(oldest);
+        }
+        self.dirty.set(true);
+    }
+
+    pub fn add_message<S: Into<String>>(&self, message: S) {
+        self.add_line(Line::new(message.into(), None, Local::now()));
+    }
+
+    /// Adds a message with nicks in it, given by where they are in the
+    /// text, to draw in their colours.
+    pub fn add_message_with_nicks(&self, message: String, nicks: Vec<(usize, usize)>) {
+        let mut line = Line::new(message, None, Local::now());
+        line.nicks = nicks.into_iter().map(|(start, end)| (start, end, String::from(&line.text[start..end]))).collect();
+        self.add_line(line);
+    }
+
+    fn add_line(&self, line: Line) {
+        let settings = self.settings.borrow();
+        if settings.logging.enabled {
+            let text = line.render(&settings.ui.timestamp_format);
+            log::append(&settings.logging.directory(), &self.name, &text).ok();
+        }
+        self.messages.add_message(line, settings.ui.scrollback);
+        // Keep the scrolled view in place, unless it's at the oldest message
+        // and that was dropped to make room.
+        if self.scroll.get() > 0 {
+            let oldest = self.messages.len().saturating_sub(1);
+            self.scroll.set(::std::cmp::min(self.scroll.get() + 1, oldest));
+        }
+
+        self.dirty.set(true);
+    }
+
+    /// Whether anything changed since the area was last drawn.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.get()
+    }
+
+    /// Marks the area to be drawn again, such as after scrolling.
+    pub fn redraw(&self) {
+        self.dirty.set(true);
+    }
+
+    /// Draws the newest messages, or the ones scrolled to, from the bottom of
+    /// `rect` up. Long messages wrap within it.
+    pub fn draw(&self, rect: Rect) {
+        self.dirty.set(false);
+        self.rows.set(rect.height);
+        self.cols.set(rect.width);
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+        let width = rect.width as usize;
+        let mut height = rect.height as usize;
+        if self.is_loading() {
+            height -= 1;
+        }
+        let settings = self.settings.borrow();
+        let theme = Theme::new(&settings.ui);
+        let storage = self.messages.storage.borrow();
+        let end = storage.len().saturating_sub(self.scroll.get());
+        let marker = self.marker.get();
+        let mut rows = Vec::with_capacity(height);
+        let mut after_unread = false;
+        for line in storage.iter().take(end).rev() {
+            let unread = marker.map_or(false, |marker| line.millis() > marker);
+            if after_unread && !unread {
+                rows.push(theme.paint("marker", &marker_row(width)));
+            }
+            after_unread = unread;
+            let format = &settings.ui.timestamp_format;
+            let wrapped = wrap(&line.styled(format, &theme), width, line.indent(format));
+            rows.extend(wrapped.into_iter().rev());
+            if rows.len() >= height {
+                break;
+            }
+        }
+        rows.truncate(height);
+        if self.is_loading() {
+            rows.push(String::from("--- Loading history… ---"));
+        }
+        while rows.len() < rect.height as usize {
+            rows.push(String::new());
+        }
+        for (i, row) in rows.iter().rev().enumerate() {
+            let padding = width.saturating_sub(visible_width(row));
+            print!("{}{}{}{}", cursor::Goto(rect.x, rect.y + i as u16), row, style::Reset, " ".repeat(padding));
+        }
+        drop(storage);
+        self.mark_read(end);
+    }
+
+    /// Notes that the messages before `end` have been on screen.
+    fn mark_read(&self, end: usize) {
+        if end == 0 {
+            return;
+        }
+        let newest = self.messages.storage.borrow().get(end - 1).map(|line| line.millis());
+        if newest > self.read_until.get() {
+            self.read_until.set(newest);
+        }
+    }
+
+    pub fn read_until(&self) -> Option<i64> {
+        self.read_until.get()
+    }
+
+    /// Restores where reading had got to. The marker goes there too.
+    pub fn set_read_until(&self, read_until: Option<i64>) {
+        self.read_until.set(read_until);
+        self.marker.set(read_until);
+    }
+
+    /// Moves the marker to below everything that's been on screen, when
+    /// leaving the window.
+    pub fn set_marker(&self) {
+        self.marker.set(self.read_until.get());
+        self.dirty.set(true);
+    }
+
+    /// Scrolls so that the marker is near the top, with as many unread
+    /// messages below it as fit. Returns false if there are none.
+    pub fn scroll_to_unread(&self) -> bool {
+        let marker = match self.marker.get() {
+            Some(marker) => marker,
+            None => return false,
+        };
+        let storage = self.messages.storage.borrow();
+        let first = match storage.iter().position(|line| line.millis() > marker) {
+            Some(first) => first,
+            None => return false,
+        };
+        let width = match self.cols.get() {
+            0 => self.layout.display_rect().width as usize,
+            cols => cols as usize,
+        };
+        let height = self.page_rows();
+        let settings = self.settings.borrow();
+        // The marker takes a row too.
+        let mut used = 1;
+        let mut end = first;
+        for line in storage.iter().skip(first) {
+            let format = &settings.ui.timestamp_format;
+            used += wrap(&line.render(format), width, line.indent(format)).len();
+            if used > height && end > first {
+                break;
+            }
+            end += 1;
+        }
+        self.scroll.set(storage.len() - end);
+        self.dirty.set(true);
+        true
+    }
+
+    /// Annotates the message with the given msgid. Returns false if it's not
+    /// in the scrollback.
+    pub fn react(&self, msgid: &str, reaction: &str, nick: &str) -> bool {
+        let mut storage = self.messages.storage.borrow_mut();
+        match storage.iter_mut().find(|line| line.msgid.as_ref().map(|x| &x[..]) == Some(msgid)) {
+            Some(line) => {
+                line.react(reaction, nick);
+                self.dirty.set(true);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn oldest_msgid(&self) -> Option<String> {
+        let storage = self.messages.storage.borrow();
+        storage.iter().filter_map(|line| line.msgid.clone()).next()
+    }
+
+    pub fn set_loading(&self, loading: bool) {
+        self.loading.set(if loading { Some(Instant::now()) } else { None });
+        self.dirty.set(true);
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.loading.get().is_some()
+    }
+
+    /// How long history has been loading for.
+    pub fn loading_for(&self) -> Option<Duration> {
+        self.loading.get().map(|since| since.elapsed())
+    }
+
+    /// The height of the pane the area was last drawn in.
+    fn page_rows(&self) -> usize {
+        match self.rows.get() {
+            0 => self.layout.display_rows() as usize,
+            rows => rows as usize,
+        }
+    }
+
+    pub fn scrolled(&self) -> usize {
+        self.scroll.get()
+    }
+
+    /// Returns true if the oldest message is in view.
+    pub fn scroll_up(&self) -> bool {
+        let height = self.page_rows();
+        let len = self.messages.len();
+        let scroll = ::std::cmp::min(self.scroll.get() + height / 2, len.saturating_sub(1));
+        self.scroll.set(scroll);
+        len.saturating_sub(scroll) <= height
+    }
+
+    pub fn scroll_down(&self) {
+        let scroll = self.scroll.get().saturating_sub(self.page_rows() / 2);
+        self.scroll.set(scroll);
+    }
+}
+
+/// The items that can go in a message template, as `{name}`.
+pub const TEMPLATE_ITEMS: &'static [&'static str] = &["nick", "target", "message"];
+
+/// Checks that a message template only uses known items, and has the message
+/// in it.
+pub fn check_template(template: &str) -> Result<(), String> {
+    let mut rest = template;
+    let mut has_message = false;
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => return Err(format!("unclosed `{{` in message template `{}`", template)),
+        };
+        let name = &rest[start + 1..end];
+        if !TEMPLATE_ITEMS.contains(&name) {
+            return Err(format!("unknown message template item `{{{}}}`", name));
+        }
+        has_message = has_message || name == "message";
+        rest = &rest[end + 1..];
+    }
+    if !has_message {
+        return Err(format!("message template `{}` has no `{{message}}`", template));
+    }
+    Ok(())
+}
+
+/// Fills in a message template. Returns the text, where the nick is in it,
+/// and the column the message starts at.
+fn fill_template(template: &str, nick: &str, prefix: &str, target: &str, message: &str,
+                 format: &MessageFormat) -> (String, Vec<(usize, usize, String)>, Option<usize>) {
+    let mut text = String::new();
+    let mut nicks = Vec::new();
+    let mut column = None;
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        text.push_str(&rest[..start]);
+        match &rest[start + 1..end] {
+            "nick" => {
+                let (field, nick_start, nick_end) = nick_field(nick, prefix, format);
+                nicks.push((text.len() + nick_start, text.len() + nick_end, String::from(nick)));
+                text.push_str(&field);
+            }
+            "target" => text.push_str(target),
+            "message" => {
+                if column.is_none() {
+                    column = Some(UnicodeWidthStr::width(&text[..]));
+                }
+                text.push_str(message);
+            }
+            _ => {}
+        }
+        rest = &rest[end + 1..];
+    }
+    text.push_str(rest);
+    (text, nicks, column)
+}
+
+/// The nick as it goes in a template, after its mode prefix. With a nick
+/// column, it's padded on the left to the column's width, or cut to fit with
+/// the truncation marker after it. Returns it with where the nick, marker
+/// and all, is in it.
+fn nick_field(nick: &str, prefix: &str, format: &MessageFormat) -> (String, usize, usize) {
+    let prefix = if format.mode_prefix { prefix } else { "" };
+    let width = format.nick_width;
+    let full = UnicodeWidthStr::width(prefix) + UnicodeWidthStr::width(nick);
+    let (nick, marker) = if width == 0 || full <= width {
+        (String::from(nick), "")
+    } else {
+        let marker = &format.truncation_marker[..];
+        let room = width.saturating_sub(UnicodeWidthStr::width(prefix) + UnicodeWidthStr::width(marker));
+        let mut cut = String::new();
+        let mut used = 0;
+        for ch in nick.chars() {
+            let ch_width = UnicodeWidthChar::width(ch).unwrap_or(0);
+            if used + ch_width > room {
+                break;
+            }
+            cut.push(ch);
+            used += ch_width;
+        }
+        (cut, marker)
+    };
+    let used = UnicodeWidthStr::width(prefix) + UnicodeWidthStr::width(&nick[..]) + UnicodeWidthStr::width(marker);
+    // A wide character that didn't fit leaves a column to pad too.
+    let padding = " ".repeat(width.saturating_sub(used));
+    let start = padding.len() + prefix.len();
+    let end = start + nick.len() + marker.len();
+    (format!("{}{}{}{}", padding, prefix, nick, marker), start, end)
+}
+
+/// The text of a CTCP ACTION, as sent with /me.
+fn action_text(message: &str) -> Option<&str> {
+    let start = "\x01ACTION ";
+    if !message.starts_with(start) {
+        return None;
+    }
+    Some(message[start.len()..].trim_right_matches(&['\x01', '\r', '\n'][..]))
+}
+
+/// What a line is, for the style it's drawn in.
+#[derive(Copy, Clone, PartialEq)]
+enum Kind {
+    Plain,
+    Message { own: bool, highlight: bool },
+    JoinPart,
+    Error,
+}
+
+struct Line {
+    text: String,
+    msgid: Option<String>,
+    timestamp: DateTime<Local>,
+    reactions: Vec<(String, Vec<String>)>,
+    kind: Kind,
+    /// Where nicks are in `text`, in order, and whose they are, to draw
+    /// them in their colours. A nick may be cut short in the text.
+    nicks: Vec<(usize, usize, String)>,
+    /// The column the message starts at after its template, for lining up
+    /// wrapped rows with it.
+    message_column: Option<usize>,
+}
+
+impl Line {
+    fn new(text: String, msgid: Option<&str>, timestamp: DateTime<Local>) -> Line {
+        Line {
+            text: text,
+            msgid: msgid.map(String::from),
+            timestamp: timestamp,
+            reactions: Vec::new(),
+            kind: Kind::Plain,
+            nicks: Vec::new(),
+            message_column: None,
         }
     }
 
-    pub fn show_event(&self, event: &ChatEvent) {
+    /// When it was sent, in milliseconds since the epoch.
+    fn millis(&self) -> i64 {
+        self.timestamp.timestamp() * 1000 + self.timestamp.timestamp_subsec_millis() as i64
+    }
+
+    /// `prefix` is the sender's mode prefix in the channel, such as `@`.
+    fn from_event(event: &ChatEvent, highlight: bool, prefix: &str, format: &MessageFormat) -> Line {
         use irc_lib::client::data::Command::*;
         let from = event.source_nickname().unwrap_or("");
-        let mut message = match event.message.command {
-            PRIVMSG(ref target, ref msg) => format!("{} <{}> {}", target, from, msg),
-            NOTICE(ref target, ref msg) => format!("!{} <{}> {}", target, from, msg),
-            JOIN(ref channel, _, _) => format!("{} has joined {}", from, channel),
-            NICK(ref new_nick) => format!("{} is now known as {}", from, new_nick),
-            _ => format!("{}", event.message),
+        let at_start = vec![(0, from.len(), String::from(from))];
+        let mut column = None;
+        let (mut text, kind, nicks) = match event.message.command {
+            PRIVMSG(ref target, ref msg) | NOTICE(ref target, ref msg) => {
+                let msg = msg.trim_right_matches(&['\r', '\n'][..]);
+                let (template, msg) = match (&event.message.command, action_text(msg)) {
+                    (&NOTICE(..), _) => (&format.notice, msg),
+                    (_, Some(text)) => (&format.action, text),
+                    (_, None) => (&format.message, msg),
+                };
+                let (text, nicks, message_column) = fill_template(template, from, prefix, target, msg, format);
+                column = message_column;
+                (text, Kind::Message { own: event.about_self, highlight: highlight }, nicks)
+            }
+            JOIN(ref channel, _, _) => (format!("{} has joined {}", from, channel), Kind::JoinPart, at_start),
+            PART(ref channel, ref reason) => {
+                let mut text = format!("{} has left {}", from, channel);
+                if let Some(ref reason) = *reason {
+                    text.push_str(&format!(" ({})", reason));
+                }
+                (text, Kind::JoinPart, at_start)
+            }
+            QUIT(ref reason) => {
+                let mut text = format!("{} has quit", from);
+                if let Some(ref reason) = *reason {
+                    text.push_str(&format!(" ({})", reason));
+                }
+                (text, Kind::JoinPart, at_start)
+            }
+            NICK(ref new_nick) => (format!("{} is now known as {}", from, new_nick), Kind::Plain, at_start),
+            TOPIC(ref channel, Some(ref topic)) => {
+                (format!("{} changed the topic of {} to: {}", from, channel, topic), Kind::Plain, at_start)
+            }
+            ERROR(ref message) => (format!("Error: {}", message), Kind::Error, Vec::new()),
+            Response(ref response, _, _) if response.is_error() => (format!("{}", event.message), Kind::Error, Vec::new()),
+            _ => (format!("{}", event.message), Kind::Plain, Vec::new()),
         };
-        while message.ends_with(&['\r', '\n'][..]) {
-            message.pop();
+        while text.ends_with(&['\r', '\n'][..]) {
+            text.pop();
         }
-        self.add_message(message);
+        let mut line = Line::new(text, event.tags.get("msgid"), event.timestamp);
+        line.kind = kind;
+        line.nicks = nicks;
+        line.message_column = column;
+        line
     }
 
-    pub fn add_message<S: Into<String>>(&self, message: S) {
-        let message = message.into();
-        self.messages.add_message(message.into());
-
-        let to_be_displayed = self.to_be_displayed.get();
-        self.to_be_displayed.set(to_be_displayed + 1);
+    /// How far rows after the first are indented, to line up with the
+    /// message.
+    fn indent(&self, timestamp_format: &str) -> usize {
+        match self.message_column {
+            Some(column) => {
+                let timestamp = self.timestamp.format(timestamp_format).to_string();
+                UnicodeWidthStr::width(&timestamp[..]) + 1 + column
+            }
+            None => 0,
+        }
     }
 
-    pub fn self_message(&self, message: &str) {
-        self.add_message(format!("<> {}", message));
+    fn react(&mut self, reaction: &str, nick: &str) {
+        match self.reactions.iter().position(|&(ref x, _)| x == reaction) {
+            Some(i) => {
+                let nicks = &mut self.reactions[i].1;
+                if !nicks.iter().any(|x| x == nick) {
+                    nicks.push(String::from(nick));
+                }
+            }
+            None => self.reactions.push((String::from(reaction), vec![String::from(nick)])),
+        }
     }
 
-    pub fn update_display(&self) {
-        let messages = self.messages.storage.borrow();
-        let to_be_displayed = self.to_be_displayed.get();
-        for i in messages.len().saturating_sub(to_be_displayed) .. messages.len() {
-            let message = &messages[i];
-            let (_, max_y) = termion::terminal_size().unwrap();
-            print!("{}{}", cursor::Goto(1, max_y - 1), clear::AfterCursor);
-            print!("{}\n\n", message);
+    /// The timestamp is formatted when drawing, so that a changed format
+    /// applies to the whole scrollback.
+    fn render(&self, timestamp_format: &str) -> String {
+        let mut text = format!("{} {}", self.timestamp.format(timestamp_format), self.text);
+        for &(ref reaction, ref nicks) in &self.reactions {
+            text.push_str(&format!(" [{} {}]", reaction, nicks.join(", ")));
         }
-        self.to_be_displayed.set(0);
+        text
     }
 
-    pub fn redraw(&self) {
-        let (_, max_y) = termion::terminal_size().unwrap();
-        print!("{}{}", clear::All, cursor::Goto(1, max_y));
-        self.messages.for_all(|message| print!("{}\r\n", message));
-        print!("\n");
-        self.to_be_displayed.set(0);
+    /// The line as drawn, in the theme's styles.
+    fn styled(&self, timestamp_format: &str, theme: &Theme) -> String {
+        let (body, own) = match self.kind {
+            Kind::Plain => ("message", false),
+            Kind::Message { highlight: true, own } => ("highlight", own),
+            Kind::Message { own, .. } => ("message", own),
+            Kind::JoinPart => ("join_part", false),
+            Kind::Error => ("error", false),
+        };
+        let depth = theme.depth();
+        let body = theme.style(body);
+        let timestamp = self.timestamp.format(timestamp_format).to_string();
+        let mut text = format!("{} {}", theme.paint("timestamp", &timestamp), body.ansi(depth));
+        let mut done = 0;
+        for &(start, end, ref nick) in &self.nicks {
+            let style = if own { theme.style("own_nick") } else { theme.nick_style(nick) };
+            text.push_str(&self.text[done..start]);
+            text.push_str(&format!("{}{}{}", style.over(&body).ansi(depth), &self.text[start..end], body.ansi(depth)));
+            done = end;
+        }
+        text.push_str(&self.text[done..]);
+        for &(ref reaction, ref nicks) in &self.reactions {
+            text.push_str(&format!(" [{} {}]", reaction, nicks.join(", ")));
+        }
+        text.push_str(&style::Reset.to_string());
+        text
     }
 }
 
 struct Messages {
-    max_len: usize,
-    storage: RefCell<VecDeque<String>>,
+    storage: RefCell<VecDeque<Line>>,
 }
 
 impl Messages {
-    fn with_max(max_len: usize) -> Messages {
+    fn new() -> Messages {
         Messages {
-            max_len: max_len,
             storage: RefCell::new(VecDeque::with_capacity(100)),
         }
     }
 
-    fn add_message(&self, message: String) {
+    /// The length limit is passed in, so that a reloaded scrollback setting
+    /// applies to existing windows.
+    fn add_message(&self, message: Line, max_len: usize) {
         let mut storage = self.storage.borrow_mut();
-        while storage.len() >= self.max_len {
+        while !storage.is_empty() && storage.len() >= max_len {
             storage.pop_front();
         }
         storage.push_back(message);
     }
 
-    fn for_all<F: FnMut(&str)>(&self, mut closure: F) {
+    /// Inserts before the first message that's newer. When the scrollback
+    /// is full, the oldest message goes, which may be this one.
+    fn insert(&self, message: Line, max_len: usize) {
+        let mut storage = self.storage.borrow_mut();
+        let position = storage.iter()
+            .position(|line| line.timestamp > message.timestamp)
+            .unwrap_or(storage.len());
+        if storage.len() >= max_len {
+            if position == 0 {
+                return;
+            }
+            storage.pop_front();
+            storage.insert(position - 1, message);
+        } else {
+            storage.insert(position, message);
+        }
+    }
+
+    fn find_msgid(&self, msgid: &str) -> bool {
         let storage = self.storage.borrow();
-        for message in storage.iter() {
-            closure(message);
+        storage.iter().any(|line| line.msgid.as_ref().map(|x| &x[..]) == Some(msgid))
+    }
+
+    fn len(&self) -> usize {
+        self.storage.borrow().len()
+    }
+}
+
+/// The line between read and unread messages, across the whole width.
+fn marker_row(width: usize) -> String {
+    let label = "─── new messages ";
+    let label_width = UnicodeWidthStr::width(label);
+    if width <= label_width {
+        return "─".repeat(width);
+    }
+    format!("{}{}", label, "─".repeat(width - label_width))
+}
+
+/// Splits text into rows of at most `width` columns. Control characters
+/// and escape sequences take no room, and each row starts with the escape
+/// sequences last seen, so that styles carry on across rows. Rows after the
+/// first are indented by `indent` columns, unless that would leave them less
+/// than half the width.
+fn wrap(text: &str, width: usize, indent: usize) -> Vec<String> {
+    let indent = if indent * 2 > width { String::new() } else { " ".repeat(indent) };
+    let mut rows = Vec::new();
+    let mut row = String::new();
+    let mut used = 0;
+    let mut style = String::new();
+    let mut in_escape = false;
+    let mut after_escape = false;
+    for ch in text.chars() {
+        if ch == '\x1b' || in_escape {
+            // Each style starts with a reset, so the last run of sequences
+            // is all it takes to carry one over.
+            if ch == '\x1b' {
+                if !after_escape {
+                    style.clear();
+                }
+                in_escape = true;
+                after_escape = true;
+            } else if ch.is_ascii_alphabetic() {
+                in_escape = false;
+            }
+            style.push(ch);
+            row.push(ch);
+            continue;
+        }
+        after_escape = false;
+        let ch_width = UnicodeWidthChar::width(ch).unwrap_or(0);
+        if used + ch_width > width && used > 0 {
+            rows.push(row);
+            row = format!("{}{}", indent, style);
+            used = indent.len();
         }
+        row.push(ch);
+        used += ch_width;
+    }
+    rows.push(row);
+    rows
+}
+
+/// How many columns a row takes, leaving out escape sequences.
+fn visible_width(row: &str) -> usize {
+    let mut width = 0;
+    let mut in_escape = false;
+    for ch in row.chars() {
+        if ch == '\x1b' {
+            in_escape = true;
+        } else if in_escape {
+            in_escape = !ch.is_ascii_alphabetic();
+        } else {
+            width += UnicodeWidthChar::width(ch).unwrap_or(0);
+        }
+    }
+    width
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Local, TimeZone};
+
+    use config::MessageFormat;
+
+    use super::{Line, Messages, check_template, fill_template, nick_field, wrap};
+
+    fn messages(texts: &[(&str, i64)], max_len: usize) -> Messages {
+        let messages = Messages::new();
+        for &(text, seconds) in texts {
+            messages.add_message(Line::new(String::from(text), None, Local.timestamp(seconds, 0)), max_len);
+        }
+        messages
+    }
+
+    fn texts(messages: &Messages) -> Vec<String> {
+        messages.storage.borrow().iter().map(|line| line.text.clone()).collect()
+    }
+
+    fn column(width: usize) -> MessageFormat {
+        MessageFormat { nick_width: width, ..MessageFormat::default() }
+    }
+
+    #[test]
+    fn checks_templates() {
+        assert!(check_template("<{nick}> {message}").is_ok());
+        assert!(check_template("{target} {message}").is_ok());
+        assert!(check_template("<{nick}>").is_err());
+        assert!(check_template("<{nick}> {text}").is_err());
+        assert!(check_template("<{nick> {message}").is_err());
+    }
+
+    #[test]
+    fn nicks_without_a_column() {
+        assert_eq!(nick_field("bob", "@", &column(0)), (String::from("@bob"), 1, 4));
+        let format = MessageFormat { mode_prefix: false, ..column(0) };
+        assert_eq!(nick_field("bob", "@", &format), (String::from("bob"), 0, 3));
+    }
+
+    #[test]
+    fn pads_nicks_to_the_column() {
+        assert_eq!(nick_field("bob", "@", &column(8)), (String::from("    @bob"), 5, 8));
+        assert_eq!(nick_field("bob", "", &column(3)), (String::from("bob"), 0, 3));
+    }
+
+    #[test]
+    fn cuts_long_nicks() {
+        let (field, start, end) = nick_field("alexander", "+", &column(6));
+        assert_eq!(field, "+alex…");
+        assert_eq!(&field[start..end], "alex…");
+    }
+
+    #[test]
+    fn cuts_wide_nicks_by_columns() {
+        // Three of these would take six columns, leaving none for the
+        // marker, so the column left over is padded.
+        let (field, start, end) = nick_field("日本語です", "", &column(6));
+        assert_eq!(field, " 日本…");
+        assert_eq!(&field[start..end], "日本…");
+    }
+
+    #[test]
+    fn fills_templates() {
+        let format = column(5);
+        let (text, nicks, message_column) = fill_template("<{nick}> {message}", "bob", "@", "#rust", "hi", &format);
+        assert_eq!(text, "< @bob> hi");
+        assert_eq!(nicks, vec![(3, 6, String::from("bob"))]);
+        assert_eq!(message_column, Some(8));
+        let (text, _, _) = fill_template("{target}: {message}", "bob", "", "#rust", "hi", &format);
+        assert_eq!(text, "#rust: hi");
+    }
+
+    #[test]
+    fn wraps_to_the_pane_width() {
+        assert_eq!(wrap("", 4, 0), vec![""]);
+        assert_eq!(wrap("abcd", 4, 0), vec!["abcd"]);
+        assert_eq!(wrap("abcdefghij", 4, 0), vec!["abcd", "efgh", "ij"]);
+        assert_eq!(wrap("abc", 1, 0), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn measures_rows_without_escapes() {
+        assert_eq!(super::visible_width("\x1b[1;31mab\x1b[0m日"), 4);
+        assert_eq!(super::visible_width(""), 0);
+    }
+
+    #[test]
+    fn wraps_to_the_indent() {
+        assert_eq!(wrap("abcdefgh", 4, 0), vec!["abcd", "efgh"]);
+        assert_eq!(wrap("ab cdefghijkl", 8, 3), vec!["ab cdefg", "   hijkl"]);
+        // An indent taking more than half the row is left out.
+        assert_eq!(wrap("abcdefgh", 4, 3), vec!["abcd", "efgh"]);
+    }
+
+    #[test]
+    fn wrapping_skips_escapes_and_carries_styles() {
+        let rows = wrap("\x1b[1mabcdef", 3, 0);
+        assert_eq!(rows, vec!["\x1b[1mabc", "\x1b[1mdef"]);
+        assert_eq!(super::visible_width(&rows[0]), 3);
+        // Wide characters don't get split across rows.
+        assert_eq!(wrap("a日本", 2, 0), vec!["a", "日", "本"]);
+    }
+
+    #[test]
+    fn inserts_history_by_time() {
+        let messages = messages(&[("a", 10), ("c", 30)], 5);
+        messages.insert(Line::new(String::from("b"), None, Local.timestamp(20, 0)), 5);
+        assert_eq!(texts(&messages), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn history_keeps_the_scrollback_to_its_limit() {
+        let messages = messages(&[("a", 10), ("c", 30), ("d", 40)], 3);
+        messages.insert(Line::new(String::from("b"), None, Local.timestamp(20, 0)), 3);
+        assert_eq!(texts(&messages), vec!["b", "c", "d"]);
+        // History older than everything in a full scrollback doesn't fit.
+        messages.insert(Line::new(String::from("z"), None, Local.timestamp(0, 0)), 3);
+        assert_eq!(texts(&messages), vec!["b", "c", "d"]);
     }
 }
@@ -1,76 +1,700 @@
-use std;
-
-use rustyline::line_buffer::LineBuffer;
-use unicode_width::UnicodeWidthStr;
+use std::cell::Cell;
+use std::cmp;
+use std::collections::VecDeque;
+use std::mem;
 
 use termion::event::Key;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use super::keymap::Action;
+use super::layout::Layout;
+use super::format::{self, Style, ansi_color, caret, parse_color, COLOR};
+use super::theme::Theme;
+
+/// How many kills are kept for Alt-Y.
+const KILL_RING_SIZE: usize = 16;
+
+/// What the previous command did, for grouping undo steps and kills.
+#[derive(Copy, Clone, PartialEq)]
+enum Last {
+    Insert,
+    Delete,
+    Kill,
+    Yank,
+    Other,
+}
+
+struct Snapshot {
+    text: String,
+    pos: usize,
+}
 
 pub struct EntryLine {
-    string: LineBuffer,
+    text: String,
+    /// The cursor, as a byte index into `text`.
+    pos: usize,
+    last: Last,
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    /// The most recent kill first.
+    kill_ring: VecDeque<String>,
+    /// The range of the last yank, and which kill it was.
+    yank: Option<(usize, usize, usize)>,
+    /// The first character shown, when scrolled sideways.
+    scroll: Cell<usize>,
+    /// The first line shown, when wrapped over several rows.
+    first_line: Cell<usize>,
+    /// What's been typed into the colour picker, while it's open.
+    color_picker: Option<String>,
 }
 
 impl EntryLine {
     pub fn new() -> EntryLine {
         EntryLine {
-            string: LineBuffer::with_capacity(512),
+            text: String::with_capacity(512),
+            pos: 0,
+            last: Last::Other,
+            undo: Vec::new(),
+            redo: Vec::new(),
+            kill_ring: VecDeque::new(),
+            yank: None,
+            scroll: Cell::new(0),
+            first_line: Cell::new(0),
+            color_picker: None,
         }
     }
 
-    pub fn key_input(&mut self, key: Key) -> Option<String> {
-        use termion::event::Key::*;
-        match key {
-            Char('\n') => {
-                let mut string = LineBuffer::with_capacity(512);
-                std::mem::swap(&mut string, &mut self.string);
-                return Some(string.into_string())
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Applies an editing action. Returns the line when it's accepted.
+    pub fn apply(&mut self, action: Action) -> Option<String> {
+        use super::keymap::Action::*;
+        let pos = self.pos;
+        let last = match action {
+            AcceptLine => {
+                self.pos = 0;
+                self.undo.clear();
+                self.redo.clear();
+                self.last = Last::Other;
+                return Some(mem::replace(&mut self.text, String::with_capacity(512)));
+            }
+            BackwardDeleteChar => {
+                let start = self.prev_char(pos);
+                self.edit(Last::Delete, |line| { line.remove(start, pos); });
+                Last::Delete
+            }
+            DeleteChar => {
+                let end = self.next_char(pos);
+                self.edit(Last::Delete, |line| { line.remove(pos, end); });
+                Last::Delete
+            }
+            BackwardChar | ViCommandMode => {
+                self.pos = self.prev_char(pos);
+                Last::Other
+            }
+            ForwardChar | ViAppendMode => {
+                self.pos = self.next_char(pos);
+                Last::Other
+            }
+            BeginningOfLine | ViInsertBeginning => {
+                self.pos = 0;
+                Last::Other
+            }
+            EndOfLine | ViAppendEol => {
+                self.pos = self.text.len();
+                Last::Other
+            }
+            BackwardWord => {
+                self.pos = self.word_start(pos, char::is_alphanumeric);
+                Last::Other
             }
-            Backspace => {
-                self.string.backspace();
+            ForwardWord => {
+                self.pos = self.word_end(pos);
+                Last::Other
             }
-            Delete => {
-                self.string.delete();
+            KillLine => {
+                let end = self.text.len();
+                self.kill(pos, end, false)
             }
-            Left => {
-                self.string.move_left();
+            UnixLineDiscard => self.kill(0, pos, true),
+            KillWord => {
+                let end = self.word_end(pos);
+                self.kill(pos, end, false)
             }
-            Right => {
-                self.string.move_right();
+            BackwardKillWord => {
+                let start = self.word_start(pos, char::is_alphanumeric);
+                self.kill(start, pos, true)
             }
-            Home => {
-                self.string.move_home();
+            UnixWordRubout => {
+                let start = self.word_start(pos, |c| !c.is_whitespace());
+                self.kill(start, pos, true)
             }
-            End => {
-                self.string.move_end();
+            Yank => self.yank(),
+            YankPop => self.yank_pop(),
+            TransposeChars => {
+                self.transpose();
+                Last::Other
             }
-            Ctrl('w') => {
-                self.string.delete_word();
+            UpcaseWord => self.change_case(|word| word.to_uppercase()),
+            DowncaseWord => self.change_case(|word| word.to_lowercase()),
+            CapitalizeWord => self.change_case(|word| {
+                let start = word.find(char::is_alphanumeric).unwrap_or(0);
+                let mut chars = word[start..].chars();
+                let first = chars.next().map(|c| c.to_uppercase().collect()).unwrap_or_else(String::new);
+                format!("{}{}{}", &word[..start], first, chars.as_str().to_lowercase())
+            }),
+            InsertBold => self.insert_code(format::BOLD),
+            InsertItalic => self.insert_code(format::ITALIC),
+            InsertUnderline => self.insert_code(format::UNDERLINE),
+            InsertReverse => self.insert_code(format::REVERSE),
+            InsertReset => self.insert_code(format::RESET),
+            InsertColor => {
+                self.color_picker = Some(String::new());
+                self.last
             }
-            Ctrl('u') => {
-                self.string.update("", 0);
+            Undo => {
+                if let Some(snapshot) = self.undo.pop() {
+                    let current = self.restore(snapshot);
+                    self.redo.push(current);
+                }
+                Last::Other
             }
-            Char(ch) => {
-                self.string.insert(ch);
+            Redo => {
+                if let Some(snapshot) = self.redo.pop() {
+                    let current = self.restore(snapshot);
+                    self.undo.push(current);
+                }
+                Last::Other
+            }
+            _ => self.last,
+        };
+        self.last = last;
+        None
+    }
+
+    pub fn insert(&mut self, ch: char) {
+        let pos = self.pos;
+        self.edit(Last::Insert, |line| {
+            line.text.insert(pos, ch);
+            line.pos = pos + ch.len_utf8();
+        });
+        self.last = Last::Insert;
+    }
+
+    fn insert_code(&mut self, code: char) -> Last {
+        self.insert(code);
+        Last::Other
+    }
+
+    pub fn is_picking_color(&self) -> bool {
+        self.color_picker.is_some()
+    }
+
+    /// Takes keys while the colour picker is open. Colours are typed as
+    /// `fg[,bg]`, and Enter inserts the colour code.
+    pub fn color_picker_key(&mut self, key: Key) {
+        let mut typed = match self.color_picker.take() {
+            Some(typed) => typed,
+            None => return,
+        };
+        match key {
+            Key::Char('\n') => {
+                self.insert(COLOR);
+                for ch in color_digits(&typed).chars() {
+                    self.insert(ch);
+                }
+                self.last = Last::Other;
+                return;
+            }
+            Key::Esc | Key::Ctrl('c') | Key::Ctrl('g') => return,
+            Key::Char(ch) => {
+                let mut candidate = typed.clone();
+                candidate.push(ch);
+                if is_color_spec(&candidate) {
+                    typed = candidate;
+                }
+            }
+            Key::Backspace => {
+                typed.pop();
             }
             _ => {}
         }
-        None
+        self.color_picker = Some(typed);
+    }
+
+    /// Draws the colour picker over the entry line.
+    fn draw_color_picker(&self, typed: &str, layout: &Layout) -> (u16, u16) {
+        use termion::{color, style};
+        let label = "Colour (fg[,bg]):";
+        print!("{}", label);
+        for n in 0..16 {
+            let background = ansi_color(n).unwrap_or(0);
+            // Black text on the light colours, white on the dark ones.
+            let foreground = match n {
+                0 | 7 | 8 | 9 | 11 | 15 => 0,
+                _ => 15,
+            };
+            print!(" {}{}{:2}{}",
+                color::Bg(color::AnsiValue(background)),
+                color::Fg(color::AnsiValue(foreground)),
+                n,
+                style::Reset);
+        }
+        let prompt = format!(" > {}", typed);
+        print!("{}", prompt);
+        // Each swatch takes three columns.
+        let x = UnicodeWidthStr::width(label) + 16 * 3 + UnicodeWidthStr::width(&prompt[..]) + 1;
+        (x as u16, layout.entry_row())
+    }
+
+    /// Runs an edit, saving the line for undo first. Like readline, a run of
+    /// typed characters, or of deleted ones, is undone in one step.
+    fn edit<F: FnOnce(&mut EntryLine)>(&mut self, kind: Last, edit: F) {
+        let before = Snapshot { text: self.text.clone(), pos: self.pos };
+        edit(self);
+        if self.text == before.text {
+            return;
+        }
+        let grouped = self.last == kind && (kind == Last::Insert || kind == Last::Delete);
+        if !grouped {
+            self.undo.push(before);
+        }
+        self.redo.clear();
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) -> Snapshot {
+        let current = Snapshot {
+            text: mem::replace(&mut self.text, snapshot.text),
+            pos: self.pos,
+        };
+        self.pos = snapshot.pos;
+        current
+    }
+
+    fn remove(&mut self, start: usize, end: usize) -> String {
+        let removed = self.text[start..end].to_owned();
+        self.text.drain(start..end);
+        self.pos = start;
+        removed
+    }
+
+    /// Removes the text into the kill ring. Consecutive kills are joined
+    /// into one entry, in the order the text was on the line.
+    fn kill(&mut self, start: usize, end: usize, backward: bool) -> Last {
+        if start == end {
+            return Last::Kill;
+        }
+        let mut killed = String::new();
+        self.edit(Last::Kill, |line| killed = line.remove(start, end));
+        if self.last == Last::Kill && !self.kill_ring.is_empty() {
+            let previous = &mut self.kill_ring[0];
+            if backward {
+                previous.insert_str(0, &killed);
+            } else {
+                previous.push_str(&killed);
+            }
+        } else {
+            self.kill_ring.push_front(killed);
+            self.kill_ring.truncate(KILL_RING_SIZE);
+        }
+        Last::Kill
+    }
+
+    fn yank(&mut self) -> Last {
+        let text = match self.kill_ring.front() {
+            Some(text) => text.clone(),
+            None => return Last::Other,
+        };
+        let start = self.pos;
+        self.edit(Last::Yank, |line| {
+            line.text.insert_str(start, &text);
+            line.pos = start + text.len();
+        });
+        self.yank = Some((start, start + text.len(), 0));
+        Last::Yank
+    }
+
+    /// Replaces the text just yanked with the kill before it.
+    fn yank_pop(&mut self) -> Last {
+        let (start, end, index) = match self.yank {
+            Some(yank) if self.last == Last::Yank => yank,
+            _ => return Last::Other,
+        };
+        let index = (index + 1) % self.kill_ring.len();
+        let text = self.kill_ring[index].clone();
+        self.edit(Last::Yank, |line| {
+            line.text.drain(start..end);
+            line.text.insert_str(start, &text);
+            line.pos = start + text.len();
+        });
+        self.yank = Some((start, start + text.len(), index));
+        Last::Yank
+    }
+
+    /// Swaps the characters around the cursor, or the last two at the end of
+    /// the line, and moves past them.
+    fn transpose(&mut self) {
+        let mut pos = self.pos;
+        if pos == self.text.len() {
+            pos = self.prev_char(pos);
+        }
+        let start = self.prev_char(pos);
+        let end = self.next_char(pos);
+        if start == pos || pos == end {
+            return;
+        }
+        self.edit(Last::Other, |line| {
+            let second = line.remove(pos, end);
+            line.text.insert_str(start, &second);
+            line.pos = end;
+        });
+    }
+
+    /// Changes the case from the cursor to the end of the word, and moves
+    /// past it.
+    fn change_case<F: FnOnce(&str) -> String>(&mut self, change: F) -> Last {
+        let start = self.pos;
+        let end = self.word_end(start);
+        let changed = change(&self.text[start..end]);
+        self.edit(Last::Other, |line| {
+            line.text.drain(start..end);
+            line.text.insert_str(start, &changed);
+            line.pos = start + changed.len();
+        });
+        Last::Other
+    }
+
+    fn prev_char(&self, pos: usize) -> usize {
+        self.text[..pos].char_indices().next_back().map(|(i, _)| i).unwrap_or(0)
+    }
+
+    fn next_char(&self, pos: usize) -> usize {
+        self.text[pos..].chars().next().map(|c| pos + c.len_utf8()).unwrap_or(pos)
+    }
+
+    /// The start of the word before `pos`, where words are runs of characters
+    /// matching `is_word`.
+    fn word_start<F: Fn(char) -> bool>(&self, pos: usize, is_word: F) -> usize {
+        let mut start = 0;
+        let chars = self.text[..pos].char_indices().rev().skip_while(|&(_, c)| !is_word(c));
+        for (i, c) in chars {
+            if !is_word(c) {
+                break;
+            }
+            start = i;
+        }
+        start
+    }
+
+    /// The end of the word after `pos`.
+    fn word_end(&self, pos: usize) -> usize {
+        let mut chars = self.text[pos..].char_indices().skip_while(|&(_, c)| !c.is_alphanumeric());
+        match chars.find(|&(_, c)| !c.is_alphanumeric()) {
+            Some((i, _)) => pos + i,
+            None => self.text.len(),
+        }
+    }
+
+    /// How many rows the entry line needs, up to `max_rows`.
+    pub fn rows(&self, layout: &Layout, limit: Option<usize>, max_rows: u16) -> u16 {
+        if max_rows <= 1 {
+            return 1;
+        }
+        let width = self.text_width(layout, limit).saturating_sub(1);
+        let lines = wrap(&self.cells(""), width).len();
+        cmp::min(lines, max_rows as usize) as u16
+    }
+
+    /// Draws the line, scrolled to keep the cursor in view. `limit` is how
+    /// many bytes a message can have, if the line is one.
+    pub fn draw(&self, layout: &Layout, limit: Option<usize>, theme: &Theme) {
+        use termion::{clear, cursor, style};
+        let row = layout.entry_row();
+        let width = self.text_width(layout, limit);
+        let base = theme.ansi("entry_line");
+        let cells = self.cells(&base);
+        let cursor = self.text[..self.pos].chars().count();
+        // Clearing fills the rows with the entry line's background.
+        print!("{}{}{}", cursor::Goto(1, row), base, clear::AfterCursor);
+        if let Some(ref typed) = self.color_picker {
+            let (x, y) = self.draw_color_picker(typed, layout);
+            print!("{}{}", cursor::Goto(x, y), cursor::Show);
+            return;
+        }
+        let (cursor_x, cursor_y) = if layout.entry_rows() > 1 {
+            self.draw_wrapped(&cells, cursor, width, layout)
+        } else {
+            (self.draw_scrolled(&cells, cursor, width), row)
+        };
+        if let Some((counter, over)) = self.counter(limit) {
+            let (cols, _) = layout.size();
+            let x = cols.saturating_sub(counter.len() as u16) + 1;
+            print!("{}", cursor::Goto(x, row));
+            if over {
+                print!("{}", theme.paint("error", &counter));
+            } else {
+                print!("{}{}", base, counter);
+            }
+        }
+        print!("{}{}{}", style::Reset, cursor::Goto(cursor_x, cursor_y), cursor::Show);
+    }
+
+    /// Draws one row, scrolled sideways, with `<` and `>` where text is cut
+    /// off. Returns the cursor's column.
+    fn draw_scrolled(&self, cells: &[(String, usize)], cursor: usize, width: usize) -> u16 {
+        use termion::style;
+        let mut scroll = self.scroll.get();
+        if cursor < scroll {
+            // Jump back far enough to show some of what's before the cursor.
+            scroll = cursor.saturating_sub(width / 4);
+        }
+        // Leave room for the cursor, and for markers on both sides.
+        while scroll < cursor && columns(&cells[scroll..cursor]) + 2 + (scroll > 0) as usize > width {
+            scroll += 1;
+        }
+        self.scroll.set(scroll);
+
+        let mut x = 0;
+        if scroll > 0 {
+            print!("<");
+            x += 1;
+        }
+        // The cursor can sit after the last character.
+        let fits = columns(&cells[scroll..]) + x < width;
+        let mut cursor_x = x;
+        for (i, &(ref cell, cell_width)) in cells.iter().enumerate().skip(scroll) {
+            if !fits && x + cell_width > width - 1 {
+                print!("{}>", style::Reset);
+                break;
+            }
+            if i == cursor {
+                cursor_x = x;
+            }
+            print!("{}", cell);
+            x += cell_width;
+        }
+        if cursor == cells.len() {
+            cursor_x = x;
+        }
+        print!("{}", style::Reset);
+        cursor_x as u16 + 1
+    }
+
+    /// Draws the line wrapped over the entry rows, scrolled by whole rows.
+    /// The last column is left for the `<` and `>` markers. Returns the
+    /// cursor's position.
+    fn draw_wrapped(&self, cells: &[(String, usize)], cursor: usize, width: usize, layout: &Layout) -> (u16, u16) {
+        use termion::{cursor, style};
+        let rows = layout.entry_rows() as usize;
+        let lines = wrap(cells, width.saturating_sub(1));
+        let cursor_line = lines.iter()
+            .position(|&(start, end)| cursor >= start && cursor < end)
+            .unwrap_or(lines.len() - 1);
+        let mut first = cmp::min(self.first_line.get(), cursor_line);
+        if cursor_line >= first + rows {
+            first = cursor_line + 1 - rows;
+        }
+        self.first_line.set(first);
+
+        let mut position = (1, layout.entry_row());
+        for (y, &(start, end)) in lines.iter().enumerate().skip(first).take(rows) {
+            let row = layout.entry_row() + (y - first) as u16;
+            print!("{}", cursor::Goto(1, row));
+            let mut x = 0;
+            let end = cmp::min(end, cells.len());
+            for (i, &(ref cell, cell_width)) in cells[start..end].iter().enumerate() {
+                if start + i == cursor {
+                    position = (x as u16 + 1, row);
+                }
+                print!("{}", cell);
+                x += cell_width;
+            }
+            print!("{}", style::Reset);
+            if y == cursor_line && cursor == cells.len() {
+                position = (x as u16 + 1, row);
+            }
+            let marker = if y == first && first > 0 {
+                Some('<')
+            } else if y == first + rows - 1 && y + 1 < lines.len() {
+                Some('>')
+            } else {
+                None
+            };
+            if let Some(marker) = marker {
+                print!("{}{}", cursor::Goto(width as u16, row), marker);
+            }
+        }
+        position
+    }
+
+    /// The columns left for text once the byte counter is drawn.
+    fn text_width(&self, layout: &Layout, limit: Option<usize>) -> usize {
+        let (cols, _) = layout.size();
+        let counter = self.counter(limit).map(|(counter, _)| counter.len() + 1).unwrap_or(0);
+        cmp::max((cols as usize).saturating_sub(counter), 4)
+    }
+
+    /// The byte counter, and whether the line is over the limit. Commands
+    /// aren't messages, so they don't get one.
+    fn counter(&self, limit: Option<usize>) -> Option<(String, bool)> {
+        match limit {
+            Some(limit) if !self.text.is_empty() && !self.text.starts_with('/') => {
+                let used = self.text.len();
+                Some((format!("{}/{}", used, limit), used > limit))
+            }
+            _ => None,
+        }
     }
 
-    pub fn draw(&self) {
-        // TODO: Handle lines longer than the screen.
-        use termion::{self, clear, cursor};
-        let (_, max_y) = termion::terminal_size().unwrap();
-        print!("{}{}", cursor::Goto(1, max_y), clear::AfterCursor);
-        for ch in self.string.chars() {
-            match ch {
-                '\u{00}' => print!("\0"),
-                '\u{01}'...'\u{1f}' => print!("^{:?}", ch),
-                '\u{7f}' => print!("^?"),
-                '\u{80}'...'\u{9f}' => print!("@{:?}", ch),
-                _ => print!("{}", ch),
+    /// Each character as drawn, and its width. Control characters are shown
+    /// in caret notation, in reverse video, and the text after formatting
+    /// codes is shown in the style they set. Plain text is drawn in `base`.
+    fn cells(&self, base: &str) -> Vec<(String, usize)> {
+        use termion::style;
+        let mut cells = Vec::with_capacity(self.text.len());
+        let mut current = Style::default();
+        // The colour numbers after a colour code are shown plainly.
+        let mut color_args = 0;
+        for (i, ch) in self.text.char_indices() {
+            if color_args > 0 {
+                color_args -= ch.len_utf8();
+                cells.push((format!("{}{}", base, ch), ch.width().unwrap_or(0)));
+                continue;
+            }
+            match caret(ch) {
+                Some(caret) => {
+                    if ch == COLOR {
+                        let (fg, bg, len) = parse_color(&self.text[i + 1..]);
+                        current.set_color(fg, bg);
+                        color_args = len;
+                    } else {
+                        current.toggle(ch);
+                    }
+                    let width = caret.len();
+                    cells.push((format!("{}{}{}", style::Reset, style::Invert, caret), width));
+                }
+                None => {
+                    let ansi = if current == Style::default() { String::from(base) } else { current.ansi() };
+                    cells.push((format!("{}{}", ansi, ch), ch.width().unwrap_or(0)));
+                }
             }
         }
-        let cursor_pos = self.string[..self.string.pos()].width() as u16 + 1;
-        print!("{}{}", cursor::Goto(cursor_pos, max_y), cursor::Show);
+        cells
+    }
+}
+
+/// Whether the text is a colour being typed: one or two digits, optionally
+/// followed by a comma and up to two more.
+fn is_color_spec(text: &str) -> bool {
+    let mut parts = text.splitn(2, ',');
+    let fg = parts.next().unwrap_or("");
+    let bg = parts.next().unwrap_or("");
+    let is_number = |part: &str| part.len() <= 2 && part.bytes().all(|b| b.is_ascii_digit());
+    !fg.is_empty() && is_number(fg) && is_number(bg)
+}
+
+/// The digits to put after the colour code. Colours always get two digits,
+/// so that text starting with a digit, or a comma and a digit, isn't taken
+/// as part of the code.
+fn color_digits(typed: &str) -> String {
+    // A trailing comma would be taken as part of the text.
+    let mut parts = typed.trim_right_matches(',').splitn(2, ',');
+    let fg = parts.next().unwrap_or("");
+    if fg.is_empty() {
+        return String::new();
+    }
+    match parts.next() {
+        Some(bg) => format!("{:0>2},{:0>2}", fg, bg),
+        None => format!("{:0>2}", fg),
+    }
+}
+
+fn columns(cells: &[(String, usize)]) -> usize {
+    cells.iter().map(|&(_, width)| width).sum()
+}
+
+/// Splits cells into lines of at most `width` columns, as `(start, end)`
+/// ranges. The cursor can sit past the last cell, so room is kept for it.
+fn wrap(cells: &[(String, usize)], width: usize) -> Vec<(usize, usize)> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut used = 0;
+    for i in 0..cells.len() + 1 {
+        let cell_width = cells.get(i).map(|&(_, width)| width).unwrap_or(1);
+        if used + cell_width > width && i > start {
+            lines.push((start, i));
+            start = i;
+            used = 0;
+        }
+        used += cell_width;
+    }
+    lines.push((start, cells.len() + 1));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use termion::event::Key;
+
+    use super::{EntryLine, color_digits, wrap};
+
+    fn cells(widths: &[usize]) -> Vec<(String, usize)> {
+        widths.iter().map(|&width| (String::new(), width)).collect()
+    }
+
+    #[test]
+    fn wraps_cells_into_rows() {
+        assert_eq!(wrap(&cells(&[1, 1, 1, 1, 1]), 3), vec![(0, 3), (3, 6)]);
+        assert_eq!(wrap(&cells(&[]), 3), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn keeps_room_for_the_cursor() {
+        // A full row leaves the cursor on a row of its own.
+        assert_eq!(wrap(&cells(&[1, 1, 1]), 3), vec![(0, 3), (3, 4)]);
+    }
+
+    #[test]
+    fn wraps_wide_cells_whole() {
+        assert_eq!(wrap(&cells(&[2, 2, 2]), 3), vec![(0, 1), (1, 2), (2, 4)]);
+        // A cell wider than a row still gets one.
+        assert_eq!(wrap(&cells(&[5]), 3), vec![(0, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn cells_take_the_width_shown() {
+        let mut entry_line = EntryLine::new();
+        for ch in "a日\x02b".chars() {
+            entry_line.insert(ch);
+        }
+        let widths: Vec<usize> = entry_line.cells("").iter().map(|&(_, width)| width).collect();
+        // The bold code is shown as ^B.
+        assert_eq!(widths, vec![1, 2, 2, 1]);
+    }
+
+    #[test]
+    fn colours_get_two_digits() {
+        assert_eq!(color_digits("4"), "04");
+        assert_eq!(color_digits("12"), "12");
+        assert_eq!(color_digits("4,2"), "04,02");
+        assert_eq!(color_digits("4,"), "04");
+        assert_eq!(color_digits(""), "");
+    }
+
+    #[test]
+    fn picked_colours_stay_apart_from_digits_after_them() {
+        let mut entry_line = EntryLine::new();
+        entry_line.insert('a');
+        entry_line.insert(' ');
+        entry_line.color_picker = Some(String::new());
+        entry_line.color_picker_key(Key::Char('4'));
+        entry_line.color_picker_key(Key::Char(','));
+        entry_line.color_picker_key(Key::Char('\n'));
+        for ch in ",2 apples".chars() {
+            entry_line.insert(ch);
+        }
+        assert_eq!(entry_line.text(), "a \x0304,2 apples");
     }
 }
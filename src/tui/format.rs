@@ -0,0 +1,190 @@
+use termion::{color, style};
+use unicode_width::UnicodeWidthChar;
+
+pub const BOLD: char = '\x02';
+pub const COLOR: char = '\x03';
+pub const RESET: char = '\x0f';
+pub const REVERSE: char = '\x16';
+pub const ITALIC: char = '\x1d';
+pub const UNDERLINE: char = '\x1f';
+
+/// The 256-colour palette index for an mIRC colour number. Only the 16
+/// standard colours are mapped.
+pub fn ansi_color(mirc: u8) -> Option<u8> {
+    let index = match mirc {
+        0 => 15,
+        1 => 0,
+        2 => 4,
+        3 => 2,
+        4 => 9,
+        5 => 1,
+        6 => 5,
+        7 => 3,
+        8 => 11,
+        9 => 10,
+        10 => 6,
+        11 => 14,
+        12 => 12,
+        13 => 13,
+        14 => 8,
+        15 => 7,
+        _ => return None,
+    };
+    Some(index)
+}
+
+/// Parses the colour numbers after `\x03`, as `fg[,bg]` with up to two
+/// digits each. Returns the colours and how many bytes they took.
+pub fn parse_color(text: &str) -> (Option<u8>, Option<u8>, usize) {
+    fn number(text: &str) -> (Option<u8>, usize) {
+        let len = text.bytes().take(2).take_while(|b| b.is_ascii_digit()).count();
+        (text[..len].parse().ok(), len)
+    }
+    let (fg, mut len) = number(text);
+    if fg.is_none() {
+        return (None, None, 0);
+    }
+    let mut bg = None;
+    if text[len..].starts_with(',') {
+        let (number, bg_len) = number(&text[len + 1..]);
+        if number.is_some() {
+            bg = number;
+            len += 1 + bg_len;
+        }
+    }
+    (fg, bg, len)
+}
+
+/// Text attributes set by mIRC formatting codes.
+#[derive(Copy, Clone, Default, PartialEq)]
+pub struct Style {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+    pub fg: Option<u8>,
+    pub bg: Option<u8>,
+}
+
+impl Style {
+    /// Applies a formatting code. Colours are set with `set_color` instead,
+    /// as they take arguments.
+    pub fn toggle(&mut self, code: char) {
+        match code {
+            BOLD => self.bold = !self.bold,
+            ITALIC => self.italic = !self.italic,
+            UNDERLINE => self.underline = !self.underline,
+            REVERSE => self.reverse = !self.reverse,
+            RESET => *self = Style::default(),
+            _ => {}
+        }
+    }
+
+    /// A colour code without numbers turns colours off.
+    pub fn set_color(&mut self, fg: Option<u8>, bg: Option<u8>) {
+        if fg.is_none() {
+            self.fg = None;
+            self.bg = None;
+            return;
+        }
+        self.fg = fg;
+        if bg.is_some() {
+            self.bg = bg;
+        }
+    }
+
+    /// The escape sequence that switches the terminal to this style from any
+    /// other.
+    pub fn ansi(&self) -> String {
+        let mut ansi = style::Reset.to_string();
+        if self.bold {
+            ansi.push_str(&style::Bold.to_string());
+        }
+        if self.italic {
+            ansi.push_str(&style::Italic.to_string());
+        }
+        if self.underline {
+            ansi.push_str(&style::Underline.to_string());
+        }
+        if self.reverse {
+            ansi.push_str(&style::Invert.to_string());
+        }
+        if let Some(fg) = self.fg.and_then(ansi_color) {
+            ansi.push_str(&color::Fg(color::AnsiValue(fg)).to_string());
+        }
+        if let Some(bg) = self.bg.and_then(ansi_color) {
+            ansi.push_str(&color::Bg(color::AnsiValue(bg)).to_string());
+        }
+        ansi
+    }
+}
+
+/// Shows a control character in caret notation, such as `^B`, or `M-^B`
+/// for the C1 controls, like `cat -v`.
+pub fn caret(ch: char) -> Option<String> {
+    let caret = match ch {
+        '\u{00}'...'\u{1f}' => format!("^{}", (ch as u8 + 0x40) as char),
+        '\u{7f}' => String::from("^?"),
+        '\u{80}'...'\u{9f}' => format!("M-^{}", (ch as u8 - 0x80 + 0x40) as char),
+        _ => return None,
+    };
+    Some(caret)
+}
+
+/// How many columns text takes once its formatting codes are hidden.
+pub fn visible_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut i = 0;
+    while let Some(ch) = text[i..].chars().next() {
+        i += ch.len_utf8();
+        if ch == COLOR {
+            i += parse_color(&text[i..]).2;
+        } else if !ch.is_control() {
+            width += UnicodeWidthChar::width(ch).unwrap_or(0);
+        }
+    }
+    width
+}
+
+/// Turns mIRC formatting codes into terminal escapes, and cuts the text down
+/// to `width` columns, with `…` where it was cut. Other control characters
+/// are dropped. Returns the text and how many columns it takes.
+pub fn render(text: &str, width: usize) -> (String, usize) {
+    let limit = if visible_width(text) > width { width.saturating_sub(1) } else { width };
+    let mut rendered = String::new();
+    let mut style = Style::default();
+    let mut used = 0;
+    let mut i = 0;
+    while let Some(ch) = text[i..].chars().next() {
+        i += ch.len_utf8();
+        match ch {
+            COLOR => {
+                let (fg, bg, len) = parse_color(&text[i..]);
+                i += len;
+                style.set_color(fg, bg);
+                rendered.push_str(&style.ansi());
+            }
+            BOLD | ITALIC | UNDERLINE | REVERSE | RESET => {
+                style.toggle(ch);
+                rendered.push_str(&style.ansi());
+            }
+            _ if ch.is_control() => {}
+            _ => {
+                let ch_width = UnicodeWidthChar::width(ch).unwrap_or(0);
+                if used + ch_width > limit {
+                    break;
+                }
+                rendered.push(ch);
+                used += ch_width;
+            }
+        }
+    }
+    if style != Style::default() {
+        rendered.push_str(&style::Reset.to_string());
+    }
+    if limit < width {
+        rendered.push('…');
+        used += 1;
+    }
+    (rendered, used)
+}
@@ -0,0 +1,404 @@
+use std::collections::{BTreeMap, HashMap};
+
+use termion::event::Key;
+
+/// Something a key can be bound to. Names follow readline where there's an
+/// equivalent.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Action {
+    AcceptLine,
+    BackwardDeleteChar,
+    DeleteChar,
+    BackwardChar,
+    ForwardChar,
+    BeginningOfLine,
+    EndOfLine,
+    BackwardWord,
+    ForwardWord,
+    KillLine,
+    KillWord,
+    BackwardKillWord,
+    UnixWordRubout,
+    UnixLineDiscard,
+    Yank,
+    YankPop,
+    TransposeChars,
+    UpcaseWord,
+    DowncaseWord,
+    CapitalizeWord,
+    InsertBold,
+    InsertColor,
+    InsertItalic,
+    InsertUnderline,
+    InsertReverse,
+    InsertReset,
+    Undo,
+    Redo,
+    NextWindow,
+    PreviousWindow,
+    /// Windows are numbered from 0, the Status window.
+    Window(usize),
+    NextActiveWindow,
+    LastWindow,
+    NextPane,
+    SelectWindow,
+    ScrollUp,
+    ScrollDown,
+    JumpToUnread,
+    ViCommandMode,
+    ViInsertMode,
+    ViAppendMode,
+    ViInsertBeginning,
+    ViAppendEol,
+}
+
+const ACTIONS: &'static [(&'static str, Action)] = &[
+    ("accept-line", Action::AcceptLine),
+    ("backward-delete-char", Action::BackwardDeleteChar),
+    ("delete-char", Action::DeleteChar),
+    ("backward-char", Action::BackwardChar),
+    ("forward-char", Action::ForwardChar),
+    ("beginning-of-line", Action::BeginningOfLine),
+    ("end-of-line", Action::EndOfLine),
+    ("backward-word", Action::BackwardWord),
+    ("forward-word", Action::ForwardWord),
+    ("kill-line", Action::KillLine),
+    ("kill-word", Action::KillWord),
+    ("backward-kill-word", Action::BackwardKillWord),
+    ("unix-word-rubout", Action::UnixWordRubout),
+    ("unix-line-discard", Action::UnixLineDiscard),
+    ("yank", Action::Yank),
+    ("yank-pop", Action::YankPop),
+    ("transpose-chars", Action::TransposeChars),
+    ("upcase-word", Action::UpcaseWord),
+    ("downcase-word", Action::DowncaseWord),
+    ("capitalize-word", Action::CapitalizeWord),
+    ("insert-bold", Action::InsertBold),
+    ("insert-color", Action::InsertColor),
+    ("insert-italic", Action::InsertItalic),
+    ("insert-underline", Action::InsertUnderline),
+    ("insert-reverse", Action::InsertReverse),
+    ("insert-reset", Action::InsertReset),
+    ("undo", Action::Undo),
+    ("redo", Action::Redo),
+    ("next-window", Action::NextWindow),
+    ("previous-window", Action::PreviousWindow),
+    ("next-active-window", Action::NextActiveWindow),
+    ("last-window", Action::LastWindow),
+    ("next-pane", Action::NextPane),
+    ("select-window", Action::SelectWindow),
+    ("scroll-up", Action::ScrollUp),
+    ("scroll-down", Action::ScrollDown),
+    ("jump-to-unread", Action::JumpToUnread),
+    ("vi-command-mode", Action::ViCommandMode),
+    ("vi-insert-mode", Action::ViInsertMode),
+    ("vi-append-mode", Action::ViAppendMode),
+    ("vi-insert-beginning", Action::ViInsertBeginning),
+    ("vi-append-eol", Action::ViAppendEol),
+];
+
+impl Action {
+    /// Parses an action name from the config. `window-N` switches to window
+    /// number N.
+    pub fn from_name(name: &str) -> Option<Action> {
+        if name.starts_with("window-") {
+            return name["window-".len()..].parse().ok().map(Action::Window);
+        }
+        ACTIONS.iter().find(|&&(x, _)| x == name).map(|&(_, action)| action)
+    }
+}
+
+/// Parses a key as written in the config: a character, `C-x` for Ctrl,
+/// `M-x` for Alt, or a name such as `Enter`, `PageUp` or `F1`.
+pub fn parse_key(name: &str) -> Option<Key> {
+    if name.starts_with("C-") && name.chars().count() == 3 {
+        // termion reports the control characters after Ctrl-Z by the digit
+        // keys that also send them on most keyboards.
+        let key = match name[2..].chars().next() {
+            Some('\\') => Key::Ctrl('4'),
+            Some(']') => Key::Ctrl('5'),
+            Some('^') => Key::Ctrl('6'),
+            Some('_') | Some('/') => Key::Ctrl('7'),
+            Some(c) => Key::Ctrl(c.to_ascii_lowercase()),
+            None => return None,
+        };
+        return Some(key);
+    }
+    if name.starts_with("M-") && name.chars().count() == 3 {
+        return name[2..].chars().next().map(Key::Alt);
+    }
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(Key::Char(c));
+    }
+    let key = match &name.to_lowercase()[..] {
+        "enter" | "return" => Key::Char('\n'),
+        "tab" => Key::Char('\t'),
+        "space" => Key::Char(' '),
+        "backspace" => Key::Backspace,
+        "m-backspace" => Key::Alt('\x7f'),
+        "delete" => Key::Delete,
+        "insert" => Key::Insert,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        "esc" | "escape" => Key::Esc,
+        name if name.starts_with('f') => match name[1..].parse() {
+            Ok(n) if n >= 1 && n <= 12 => Key::F(n),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Checks a `[keybindings]` entry, for reporting errors when the config is
+/// loaded.
+pub fn check_binding(key: &str, action: &str) -> Result<(), String> {
+    parse_binding(key, action).map(|_| ())
+}
+
+/// Parses a `[keybindings]` entry. The action is None for `none`.
+fn parse_binding(key: &str, action: &str) -> Result<(Key, Option<Action>), String> {
+    let parsed_key = match parse_key(key) {
+        Some(parsed_key) => parsed_key,
+        None => return Err(format!("unknown key `{}`", key)),
+    };
+    match Action::from_name(action) {
+        Some(action) => Ok((parsed_key, Some(action))),
+        None if action == "none" => Ok((parsed_key, None)),
+        None => Err(format!("unknown action `{}` for key `{}`", action, key)),
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Mode {
+    /// Emacs-style editing, which is also vi's insert mode.
+    Insert,
+    ViCommand,
+}
+
+/// Maps keys to actions. Keys bound to nothing insert themselves, except in
+/// vi command mode.
+pub struct Keymap {
+    insert: HashMap<Key, Action>,
+    vi_command: HashMap<Key, Action>,
+    vi_mode: bool,
+    mode: Mode,
+}
+
+impl Keymap {
+    /// Builds the default table, with the user's `[keybindings]` on top.
+    /// Binding a key to `none` unbinds it. Fails on the first entry that
+    /// doesn't parse.
+    pub fn new(vi_mode: bool, overrides: &BTreeMap<String, String>) -> Result<Keymap, String> {
+        let mut insert = emacs_table();
+        if vi_mode {
+            insert.insert(Key::Esc, Action::ViCommandMode);
+        }
+        for (key, action) in overrides {
+            match try!(parse_binding(key, action)) {
+                (key, Some(action)) => {
+                    insert.insert(key, action);
+                }
+                (key, None) => {
+                    insert.remove(&key);
+                }
+            }
+        }
+        Ok(Keymap {
+            insert: insert,
+            vi_command: vi_command_table(),
+            vi_mode: vi_mode,
+            mode: Mode::Insert,
+        })
+    }
+
+    pub fn action(&self, key: Key) -> Option<Action> {
+        if self.mode == Mode::ViCommand {
+            if let Some(&action) = self.vi_command.get(&key) {
+                return Some(action);
+            }
+            // Window and scrolling keys work in both modes.
+            return match self.insert.get(&key) {
+                Some(&action) if !is_editing(action) => Some(action),
+                _ => None,
+            };
+        }
+        self.insert.get(&key).cloned()
+    }
+
+    /// Whether an unbound key should insert its character.
+    pub fn inserts(&self) -> bool {
+        self.mode == Mode::Insert
+    }
+
+    pub fn set_command_mode(&mut self, command: bool) {
+        if self.vi_mode {
+            self.mode = if command { Mode::ViCommand } else { Mode::Insert };
+        }
+    }
+}
+
+fn is_editing(action: Action) -> bool {
+    use self::Action::*;
+    match action {
+        NextWindow | PreviousWindow | Window(_) | NextActiveWindow | LastWindow | NextPane |
+        SelectWindow | ScrollUp | ScrollDown | JumpToUnread => false,
+        _ => true,
+    }
+}
+
+fn emacs_table() -> HashMap<Key, Action> {
+    use self::Action::*;
+    let mut table = HashMap::new();
+    table.insert(Key::Char('\n'), AcceptLine);
+    table.insert(Key::Backspace, BackwardDeleteChar);
+    table.insert(Key::Ctrl('h'), BackwardDeleteChar);
+    table.insert(Key::Delete, DeleteChar);
+    table.insert(Key::Ctrl('d'), DeleteChar);
+    table.insert(Key::Left, BackwardChar);
+    table.insert(Key::Right, ForwardChar);
+    table.insert(Key::Ctrl('f'), ForwardChar);
+    table.insert(Key::Home, BeginningOfLine);
+    table.insert(Key::Ctrl('a'), BeginningOfLine);
+    table.insert(Key::End, EndOfLine);
+    table.insert(Key::Ctrl('e'), EndOfLine);
+    // The input thread reports Ctrl-Left and Ctrl-Right as these.
+    table.insert(Key::Alt('b'), BackwardWord);
+    table.insert(Key::Alt('f'), ForwardWord);
+    table.insert(Key::Ctrl('k'), KillLine);
+    table.insert(Key::Alt('d'), KillWord);
+    table.insert(Key::Alt('\x7f'), BackwardKillWord);
+    table.insert(Key::Ctrl('w'), UnixWordRubout);
+    table.insert(Key::Ctrl('u'), UnixLineDiscard);
+    table.insert(Key::Ctrl('y'), Yank);
+    table.insert(Key::Alt('y'), YankPop);
+    table.insert(Key::Ctrl('t'), TransposeChars);
+    table.insert(Key::Alt('u'), UpcaseWord);
+    table.insert(Key::Alt('l'), DowncaseWord);
+    table.insert(Key::Alt('c'), CapitalizeWord);
+    table.insert(Key::Ctrl('z'), Undo);
+    table.insert(Key::Alt('_'), Redo);
+    // Formatting, with the keys mIRC uses, except for colours, which take
+    // Ctrl-C as in irssi so that Ctrl-K can kill the line.
+    table.insert(Key::Ctrl('b'), InsertBold);
+    table.insert(Key::Ctrl('c'), InsertColor);
+    table.insert(Key::Ctrl('7'), InsertUnderline);
+    table.insert(Key::Ctrl('5'), InsertItalic);
+    table.insert(Key::Ctrl('o'), InsertReset);
+    table.insert(Key::Ctrl('n'), NextWindow);
+    table.insert(Key::Ctrl('p'), PreviousWindow);
+    table.insert(Key::Alt('a'), NextActiveWindow);
+    table.insert(Key::Ctrl('6'), LastWindow);
+    table.insert(Key::Alt('o'), NextPane);
+    table.insert(Key::Alt('s'), SelectWindow);
+    table.insert(Key::PageUp, ScrollUp);
+    table.insert(Key::PageDown, ScrollDown);
+    table.insert(Key::Alt('j'), JumpToUnread);
+    // Alt-1 to Alt-9 are windows 1 to 9, and Alt-0 is window 10.
+    for n in 1..10 {
+        let digit = (b'0' + n as u8) as char;
+        table.insert(Key::Alt(digit), Window(n));
+    }
+    table.insert(Key::Alt('0'), Window(10));
+    table
+}
+
+fn vi_command_table() -> HashMap<Key, Action> {
+    use self::Action::*;
+    let mut table = HashMap::new();
+    table.insert(Key::Char('\n'), AcceptLine);
+    table.insert(Key::Char('h'), BackwardChar);
+    table.insert(Key::Left, BackwardChar);
+    table.insert(Key::Backspace, BackwardChar);
+    table.insert(Key::Char('l'), ForwardChar);
+    table.insert(Key::Right, ForwardChar);
+    table.insert(Key::Char(' '), ForwardChar);
+    table.insert(Key::Char('0'), BeginningOfLine);
+    table.insert(Key::Char('^'), BeginningOfLine);
+    table.insert(Key::Home, BeginningOfLine);
+    table.insert(Key::Char('$'), EndOfLine);
+    table.insert(Key::End, EndOfLine);
+    table.insert(Key::Char('x'), DeleteChar);
+    table.insert(Key::Delete, DeleteChar);
+    table.insert(Key::Char('X'), BackwardDeleteChar);
+    table.insert(Key::Char('w'), ForwardWord);
+    table.insert(Key::Char('b'), BackwardWord);
+    table.insert(Key::Char('D'), KillLine);
+    table.insert(Key::Char('p'), Yank);
+    table.insert(Key::Char('u'), Undo);
+    table.insert(Key::Ctrl('r'), Redo);
+    table.insert(Key::Char('i'), ViInsertMode);
+    table.insert(Key::Char('a'), ViAppendMode);
+    table.insert(Key::Char('I'), ViInsertBeginning);
+    table.insert(Key::Char('A'), ViAppendEol);
+    table.insert(Key::Char('j'), ScrollDown);
+    table.insert(Key::Char('k'), ScrollUp);
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use termion::event::Key;
+
+    use super::{Action, Keymap, parse_key};
+
+    #[test]
+    fn parses_modified_keys() {
+        assert_eq!(parse_key("C-a"), Some(Key::Ctrl('a')));
+        assert_eq!(parse_key("C-A"), Some(Key::Ctrl('a')));
+        assert_eq!(parse_key("M-x"), Some(Key::Alt('x')));
+        assert_eq!(parse_key("M-Backspace"), Some(Key::Alt('\x7f')));
+        assert_eq!(parse_key("C-ab"), None);
+    }
+
+    #[test]
+    fn parses_control_punctuation_as_termion_reports_it() {
+        assert_eq!(parse_key("C-\\"), Some(Key::Ctrl('4')));
+        assert_eq!(parse_key("C-]"), Some(Key::Ctrl('5')));
+        assert_eq!(parse_key("C-^"), Some(Key::Ctrl('6')));
+        assert_eq!(parse_key("C-_"), Some(Key::Ctrl('7')));
+        assert_eq!(parse_key("C-/"), Some(Key::Ctrl('7')));
+    }
+
+    #[test]
+    fn parses_named_keys() {
+        assert_eq!(parse_key("x"), Some(Key::Char('x')));
+        assert_eq!(parse_key("Enter"), Some(Key::Char('\n')));
+        assert_eq!(parse_key("pageup"), Some(Key::PageUp));
+        assert_eq!(parse_key("Esc"), Some(Key::Esc));
+        assert_eq!(parse_key("F1"), Some(Key::F(1)));
+        assert_eq!(parse_key("F12"), Some(Key::F(12)));
+        assert_eq!(parse_key("F13"), None);
+        assert_eq!(parse_key("Foo"), None);
+    }
+
+    #[test]
+    fn overrides_bind_and_unbind_keys() {
+        let mut overrides = BTreeMap::new();
+        overrides.insert(String::from("C-x"), String::from("kill-line"));
+        overrides.insert(String::from("C-w"), String::from("none"));
+        let keymap = Keymap::new(false, &overrides).ok().unwrap();
+        assert_eq!(keymap.action(Key::Ctrl('x')), Some(Action::KillLine));
+        assert_eq!(keymap.action(Key::Ctrl('w')), None);
+        assert_eq!(keymap.action(Key::Ctrl('k')), Some(Action::KillLine));
+    }
+
+    #[test]
+    fn bad_overrides_are_errors() {
+        let mut overrides = BTreeMap::new();
+        overrides.insert(String::from("C-x"), String::from("kill-everything"));
+        assert!(Keymap::new(false, &overrides).is_err());
+        let mut overrides = BTreeMap::new();
+        overrides.insert(String::from("Hyper-x"), String::from("kill-line"));
+        assert!(Keymap::new(false, &overrides).is_err());
+    }
+}
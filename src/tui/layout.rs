@@ -0,0 +1,135 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use termion;
+
+/// A part of the screen. Rows and columns are numbered from 1, like
+/// `cursor::Goto`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// Where things go on the screen. The topic bar, if shown, takes the top row,
+/// the entry line the bottom rows and the status bar the rows above it. The
+/// sidebar, if shown, takes the left of what's between, and the panes showing
+/// windows the rest.
+pub struct Layout {
+    /// The terminal size, as (columns, rows), kept until it's resized.
+    size: Cell<(u16, u16)>,
+    entry_rows: Cell<u16>,
+    sidebar_width: Cell<u16>,
+    topic_rows: Cell<u16>,
+    status_rows: Cell<u16>,
+}
+
+pub type SharedLayout = Rc<Layout>;
+
+impl Layout {
+    pub fn new() -> Layout {
+        Layout {
+            size: Cell::new(termion::terminal_size().unwrap_or((80, 24))),
+            entry_rows: Cell::new(1),
+            sidebar_width: Cell::new(0),
+            topic_rows: Cell::new(0),
+            status_rows: Cell::new(1),
+        }
+    }
+
+    /// The terminal size, as (columns, rows).
+    pub fn size(&self) -> (u16, u16) {
+        self.size.get()
+    }
+
+    /// Asks the terminal for its size again, after it's been resized.
+    pub fn update_size(&self) {
+        if let Ok(size) = termion::terminal_size() {
+            self.size.set(size);
+        }
+    }
+
+    pub fn entry_rows(&self) -> u16 {
+        self.entry_rows.get()
+    }
+
+    /// Returns true if the height changed, in which case everything above
+    /// the entry line has to be redrawn.
+    pub fn set_entry_rows(&self, rows: u16) -> bool {
+        let changed = self.entry_rows.get() != rows;
+        self.entry_rows.set(rows);
+        changed
+    }
+
+    /// 0 while the topic bar is hidden, otherwise 1.
+    pub fn topic_rows(&self) -> u16 {
+        self.topic_rows.get()
+    }
+
+    /// Returns true if the height changed, in which case the panes have to
+    /// be redrawn.
+    pub fn set_topic_rows(&self, rows: u16) -> bool {
+        let changed = self.topic_rows.get() != rows;
+        self.topic_rows.set(rows);
+        changed
+    }
+
+    /// Returns true if the width changed, in which case the panes have to be
+    /// redrawn.
+    pub fn set_sidebar_width(&self, width: u16) -> bool {
+        let changed = self.sidebar_width.get() != width;
+        self.sidebar_width.set(width);
+        changed
+    }
+
+    /// Returns true if the height changed, in which case the panes have to
+    /// be redrawn.
+    pub fn set_status_rows(&self, rows: u16) -> bool {
+        let changed = self.status_rows.get() != rows;
+        self.status_rows.set(rows);
+        changed
+    }
+
+    /// The first row of the entry line. Rows are numbered from 1.
+    pub fn entry_row(&self) -> u16 {
+        self.status_row() + self.status_rows.get()
+    }
+
+    /// The first row of the status bar.
+    pub fn status_row(&self) -> u16 {
+        let (_, rows) = self.size();
+        rows.saturating_sub(self.entry_rows.get() + self.status_rows.get() - 1).max(1)
+    }
+
+    /// How many rows there are between the topic bar and the status bar.
+    pub fn display_rows(&self) -> u16 {
+        self.status_row().saturating_sub(1 + self.topic_rows.get())
+    }
+
+    /// The part of the screen that's split into panes.
+    pub fn display_rect(&self) -> Rect {
+        let (cols, _) = self.size();
+        // The sidebar has a line to its right.
+        let left = match self.sidebar_width.get() {
+            0 => 0,
+            width => width + 1,
+        };
+        Rect {
+            x: left + 1,
+            y: 1 + self.topic_rows.get(),
+            width: cols.saturating_sub(left),
+            height: self.display_rows(),
+        }
+    }
+
+    pub fn sidebar_rect(&self) -> Rect {
+        Rect {
+            x: 1,
+            y: 1 + self.topic_rows.get(),
+            width: self.sidebar_width.get(),
+            height: self.display_rows(),
+        }
+    }
+}
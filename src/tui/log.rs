@@ -0,0 +1,13 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Appends a line to the log file of a window.
+pub fn append(directory: &Path, window: &str, line: &str) -> io::Result<()> {
+    try!(fs::create_dir_all(directory));
+    // Channel names can contain '/', which would escape the directory.
+    let file_name: String = window.chars().map(|c| if c == '/' { '_' } else { c }).collect();
+    let path = directory.join(format!("{}.log", file_name));
+    let mut file = try!(OpenOptions::new().create(true).append(true).open(path));
+    writeln!(file, "{}", line)
+}
@@ -1,48 +1,145 @@
 mod entryline;
-mod displayarea;
+pub mod displayarea;
 mod window;
-mod statusbar;
+pub mod statusbar;
+mod nicklist;
+mod typing;
+mod log;
+mod layout;
+mod format;
+mod session;
+mod pane;
+mod sidebar;
+mod topicbar;
+mod modes;
+pub mod keymap;
+pub mod theme;
 
-use std::sync::mpsc::{Sender, Receiver};
+use std::sync::mpsc::Receiver;
 use std::io::{self, Write, stdout};
+use std::time::{Duration, Instant};
+use std::cmp;
+use std::rc::Rc;
+use std::cell::RefCell;
 
 use termion::raw::{IntoRawMode, RawTerminal};
+use termion::event::{Key, MouseButton, MouseEvent};
+use irc_lib::client::data::Message;
+use irc_lib::client::data::Command::JOIN;
 
 use self::entryline::EntryLine;
-use self::window::Windows;
-use self::statusbar::StatusBar;
+use self::window::{Windows, WindowId};
+use self::statusbar::{StatusBar, StatusInfo};
+use self::typing::TypingNotifier;
+use self::keymap::{Action, Keymap};
+use self::layout::{Layout, SharedLayout};
+use self::session::Session;
+use self::pane::Direction;
+use self::sidebar::Sidebar;
+use self::topicbar::TopicBar;
+use self::theme::Theme;
 
-use event::{Event, EventReceiver};
-use irc::command::Command;
+use cli::Args;
+use config::{self, ClientConfig};
+use event::{Event, EventReceiver, EventSender};
+use irc::{self, ServerHandles};
+use irc::misc::{irc_equal, message_limit};
+use irc::command::{Command, CommandSender};
+
+/// How often the open windows are saved, besides on quitting.
+const SESSION_SAVE_INTERVAL: u64 = 60;
+
+/// Turn mouse reporting on and off, the same way as termion's MouseTerminal.
+const MOUSE_ON: &'static str = "\x1b[?1000h\x1b[?1002h\x1b[?1015h\x1b[?1006h";
+const MOUSE_OFF: &'static str = "\x1b[?1006l\x1b[?1015l\x1b[?1002l\x1b[?1000l";
+
+/// The client config, shared by everything that draws.
+pub type Settings = Rc<RefCell<ClientConfig>>;
 
 pub struct Tui {
     entry_line: EntryLine,
+    keymap: Keymap,
     event_rx: Receiver<io::Result<Event>>,
-    irc_tx: Sender<Command>,
+    event_tx: EventSender,
+    /// Kept to apply on top of the config whenever it's loaded.
+    args: Args,
+    /// `None` until connected.
+    irc_tx: Option<CommandSender>,
+    server: Option<ServerHandles>,
+    /// `None` until it's been measured on this connection.
+    lag: Option<Duration>,
     windows: Windows,
     statusbar: StatusBar,
+    sidebar: Sidebar,
+    topicbar: TopicBar,
+    layout: SharedLayout,
     raw_stdout: RawTerminal<io::Stdout>,
     running: bool,
+    typing: TypingNotifier,
+    caps: Vec<String>,
+    last_input: Instant,
+    auto_away_active: bool,
+    settings: Settings,
+    session: Session,
+    last_save: Instant,
+    mouse: bool,
 }
 
 impl Drop for Tui {
     fn drop(&mut self) {
         use termion::{cursor, clear};
+        if self.mouse {
+            print!("{}", MOUSE_OFF);
+        }
         print!("{}\r{}", cursor::Show, clear::AfterCursor);
     }
 }
 
 impl Tui {
-    pub fn new(event_rx: EventReceiver, irc_tx: Sender<Command>) -> io::Result<Tui> {
-        Ok(Tui {
+    pub fn new(event_rx: EventReceiver, event_tx: EventSender, args: Args, config: ClientConfig) -> io::Result<Tui> {
+        let send_typing = config.ui.send_typing;
+        let mouse = config.ui.sidebar.mouse;
+        let keymap = try!(Keymap::new(config.ui.vi_mode, &config.keybindings)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, format!("Bad key binding: {}", error))));
+        let settings = Rc::new(RefCell::new(config));
+        let layout = Rc::new(Layout::new());
+        let session = Session::load();
+        let mut windows = Windows::new(settings.clone(), layout.clone());
+        windows.restore(session.windows(&network_name(&args, &settings.borrow())));
+        let mut tui = Tui {
             entry_line: EntryLine::new(),
+            keymap: keymap,
             event_rx: event_rx,
-            irc_tx: irc_tx,
-            windows: Windows::new(),
-            statusbar: StatusBar::new(),
-            raw_stdout: stdout().into_raw_mode()?,
+            event_tx: event_tx,
+            args: args,
+            irc_tx: None,
+            lag: None,
+            server: None,
+            windows: windows,
+            statusbar: StatusBar::new(settings.clone(), layout.clone()),
+            sidebar: Sidebar::new(settings.clone(), layout.clone()),
+            topicbar: TopicBar::new(settings.clone(), layout.clone()),
+            layout: layout,
+            raw_stdout: try!(stdout().into_raw_mode()),
             running: true,
-        })
+            typing: TypingNotifier::new(send_typing),
+            caps: Vec::new(),
+            last_input: Instant::now(),
+            auto_away_active: false,
+            settings: settings,
+            session: session,
+            last_save: Instant::now(),
+            mouse: false,
+        };
+        tui.set_mouse(mouse);
+        Ok(tui)
+    }
+
+    fn set_mouse(&mut self, enabled: bool) {
+        if enabled != self.mouse {
+            print!("{}", if enabled { MOUSE_ON } else { MOUSE_OFF });
+            self.mouse = enabled;
+        }
     }
 
     pub fn event_loop(&mut self) {
@@ -53,29 +150,318 @@ impl Tui {
                 Ok(event) => {
                     let event = event.unwrap();
                     match event {
-                        Event::Input(key) => {
-                            if let Some(line) = self.entry_line.key_input(key) {
-                                self.handle_line(line);
-                            }
-                        },
+                        Event::Input(_) | Event::Mouse(_) => self.note_input(),
+                        _ => {}
+                    }
+                    match event {
+                        Event::Input(key) => self.handle_key(key),
+                        Event::Mouse(mouse) => self.handle_mouse(mouse),
                         Event::Chat(event) => {
+                            let joined = match event.message.command {
+                                JOIN(ref channel, _, _) if event.about_self => Some(channel.clone()),
+                                _ => None,
+                            };
                             self.windows.handle_event(event);
-                            self.redraw();
+                            if let Some(channel) = joined {
+                                self.request_history(channel, None);
+                            }
+                        }
+                        Event::Batch(batch) => self.windows.handle_batch(batch),
+                        Event::Capabilities(caps) => {
+                            self.caps = caps;
+                        }
+                        Event::Lag(lag) => {
+                            self.lag = Some(lag);
+                        }
+                        Event::Reconnecting(reason) => {
+                            self.lag = None;
+                            // They come again with the new connection's CAP ACKs.
+                            self.caps.clear();
+                            self.windows.forget_labels();
+                            self.windows.show_status(&format!("{}. Reconnecting…", reason));
                         }
+                        Event::Tick => {
+                            if let Some((target, state)) = self.typing.tick() {
+                                self.send_quietly(Command::Typing { target: target, state: state });
+                            }
+                            self.check_auto_away();
+                            self.windows.expire_loading();
+                            if self.last_save.elapsed() >= Duration::from_secs(SESSION_SAVE_INTERVAL) {
+                                self.save_session();
+                            }
+                        }
+                        Event::Reload => self.reload(),
+                        Event::Resize => self.resize(),
                     }
                 }
                 Err(_) => break 'main_loop,
             }
             self.redraw();
         }
+        self.save_session();
+    }
+
+    fn save_session(&mut self) {
+        self.last_save = Instant::now();
+        let network = network_name(&self.args, &self.settings.borrow());
+        self.session.set_windows(&network, self.windows.saved(&network));
+        if let Err(error) = self.session.save() {
+            self.windows.show_status(&format!("Couldn't save the open windows: {}", error));
+        }
+    }
+
+    fn handle_key(&mut self, key: Key) {
+        if self.entry_line.is_picking_color() {
+            self.entry_line.color_picker_key(key);
+            return;
+        }
+        if self.sidebar.is_selecting() {
+            let count = self.windows.highest_window_index() + 1;
+            if let Some(number) = self.sidebar.select_key(key, count) {
+                self.windows.change_to(number);
+            }
+            return;
+        }
+        let action = match self.keymap.action(key) {
+            Some(action) => action,
+            None => {
+                if let Key::Char(ch) = key {
+                    if self.keymap.inserts() {
+                        self.entry_line.insert(ch);
+                        self.update_typing();
+                    }
+                }
+                return;
+            }
+        };
+        match action {
+            Action::NextWindow => self.windows.next_window(),
+            Action::PreviousWindow => self.windows.previous_window(),
+            Action::Window(number) => self.windows.change_to(number),
+            Action::NextActiveWindow => self.windows.next_active(),
+            Action::LastWindow => self.windows.last_window(),
+            Action::NextPane => self.windows.next_pane(),
+            Action::SelectWindow => {
+                if self.sidebar.width() == 0 {
+                    self.windows.show_info("The sidebar is hidden. Set enabled under [ui.sidebar] to show it.");
+                } else {
+                    self.sidebar.start_selecting(self.windows.current_window_number());
+                }
+            }
+            Action::ScrollUp => {
+                if let Some(msgid) = self.windows.current_window().scroll_up() {
+                    let target = self.windows.current_window().id().name().map(String::from);
+                    if let Some(target) = target {
+                        self.request_history(target, Some(msgid));
+                    }
+                }
+            }
+            Action::ScrollDown => self.windows.current_window().scroll_down(),
+            Action::JumpToUnread => {
+                if !self.windows.current_window().scroll_to_unread() {
+                    self.windows.show_info("No unread messages here.");
+                }
+            }
+            Action::ViCommandMode => {
+                self.keymap.set_command_mode(true);
+                self.entry_line.apply(action);
+            }
+            Action::ViInsertMode | Action::ViAppendMode | Action::ViInsertBeginning | Action::ViAppendEol => {
+                self.keymap.set_command_mode(false);
+                self.entry_line.apply(action);
+            }
+            _ => {
+                let old_text = String::from(self.entry_line.text());
+                if let Some(line) = self.entry_line.apply(action) {
+                    self.keymap.set_command_mode(false);
+                    self.handle_line(line);
+                } else if self.entry_line.text() != old_text {
+                    self.update_typing();
+                }
+            }
+        }
+    }
+
+    fn handle_mouse(&mut self, mouse: MouseEvent) {
+        if let MouseEvent::Press(MouseButton::Left, x, y) = mouse {
+            if let Some(number) = self.sidebar.click(x, y) {
+                self.windows.change_to(number);
+            }
+        }
+    }
+
+    /// Lays everything out again for the new size. The whole screen is
+    /// drawn again even if the size is the same, since the terminal may have
+    /// left it garbled.
+    fn resize(&mut self) {
+        self.layout.update_size();
+        self.windows.redraw();
     }
 
     fn redraw(&mut self) {
-        self.statusbar.draw(&self.windows);
-        self.entry_line.draw();
+        let limit = self.windows.current_target()
+            .and_then(|window| window.id().name())
+            .map(|target| message_limit(self.windows.nick(), target));
+        let max_rows = cmp::min(cmp::max(self.settings.borrow().ui.entry_rows, 1), 3);
+        let rows = self.entry_line.rows(&self.layout, limit, max_rows);
+        let sidebar_changed = self.layout.set_sidebar_width(self.sidebar.width());
+        let topic_changed = self.layout.set_topic_rows(self.topicbar.rows());
+        let status_changed = self.layout.set_status_rows(self.statusbar.rows());
+        if self.layout.set_entry_rows(rows) || sidebar_changed || topic_changed || status_changed {
+            self.windows.redraw();
+        }
+        self.windows.draw();
+        let network = network_name(&self.args, &self.settings.borrow());
+        self.sidebar.draw(&self.windows, &network);
+        self.topicbar.draw(&self.windows, &network);
+        let info = StatusInfo {
+            network: network,
+            lag: self.lag,
+            queued: self.irc_tx.as_ref().map(|irc_tx| irc_tx.queued()).unwrap_or(0),
+        };
+        self.statusbar.draw(&self.windows, &info);
+        let theme = Theme::new(&self.settings.borrow().ui);
+        self.entry_line.draw(&self.layout, limit, &theme);
         self.raw_stdout.flush().unwrap();
     }
 
+    pub fn connect(&mut self) {
+        if self.irc_tx.is_some() {
+            self.windows.show_info("Already connected.");
+            return;
+        }
+        let mut irc_config = self.settings.borrow().irc_config();
+        self.args.apply(&mut irc_config);
+        // Rejoin the channels that still have a window, such as ones restored
+        // from the last session.
+        let mut channels = irc_config.channels.take().unwrap_or_else(Vec::new);
+        for channel in self.windows.channels() {
+            if !channels.iter().any(|x| irc_equal(x, &channel)) {
+                channels.push(channel);
+            }
+        }
+        irc_config.channels = Some(channels);
+        if let Err(error) = irc::check_config(&irc_config) {
+            self.windows.show_info(&error);
+            return;
+        }
+        match irc::start(irc_config, self.event_tx.clone()) {
+            Ok((server, irc_tx)) => {
+                self.server = Some(server);
+                self.irc_tx = Some(irc_tx);
+            }
+            Err(error) => self.windows.show_info(&format!("Couldn't connect: {}", error)),
+        }
+    }
+
+    /// Re-reads the config file and applies it, reporting what changed in the
+    /// Status window. Connection settings apply the next time we connect.
+    fn reload(&mut self) {
+        let config = match ClientConfig::load(self.args.config.as_ref().map(|x| x.as_path())) {
+            Ok(config) => config,
+            Err(error) => {
+                self.windows.show_status(&format!("Couldn't reload the config: {}", error));
+                return;
+            }
+        };
+        let keymap = match Keymap::new(config.ui.vi_mode, &config.keybindings) {
+            Ok(keymap) => keymap,
+            Err(error) => {
+                self.windows.show_status(&format!("Couldn't reload the config: bad key binding: {}", error));
+                return;
+            }
+        };
+        let changes = config::diff(&self.settings.borrow(), &config);
+        if changes.is_empty() {
+            self.windows.show_status("Config reloaded, nothing changed.");
+            return;
+        }
+        if let Some((target, state)) = self.typing.set_enabled(config.ui.send_typing) {
+            self.send_quietly(Command::Typing { target: target, state: state });
+        }
+        self.keymap = keymap;
+        self.set_mouse(config.ui.sidebar.mouse);
+        *self.settings.borrow_mut() = config;
+
+        self.windows.show_status("Config reloaded:");
+        for change in changes.iter().filter(|x| !x.needs_reconnect()) {
+            self.windows.show_status(&format!("  {}", change));
+        }
+        let reconnect: Vec<_> = changes.iter().filter(|x| x.needs_reconnect()).collect();
+        if !reconnect.is_empty() {
+            let when = if self.irc_tx.is_some() { "after reconnecting" } else { "on /connect" };
+            self.windows.show_status(&format!("These take effect {}:", when));
+            for change in reconnect {
+                self.windows.show_status(&format!("  {}", change));
+            }
+        }
+        // Timestamps and colours may have changed.
+        self.windows.redraw();
+    }
+
+    fn send(&self, command: Command) {
+        match self.irc_tx {
+            Some(ref irc_tx) => irc_tx.send(command).unwrap(),
+            None => self.windows.show_info("Not connected."),
+        }
+    }
+
+    /// For commands the user didn't ask for, which shouldn't complain when
+    /// we're not connected.
+    fn send_quietly(&self, command: Command) {
+        if let Some(ref irc_tx) = self.irc_tx {
+            irc_tx.send(command).unwrap();
+        }
+    }
+
+    fn note_input(&mut self) {
+        self.last_input = Instant::now();
+        if self.auto_away_active {
+            self.auto_away_active = false;
+            self.send_quietly(Command::Away { message: None });
+        }
+    }
+
+    fn check_auto_away(&mut self) {
+        let settings = self.settings.borrow();
+        let minutes = settings.ui.auto_away_minutes;
+        if minutes == 0 || self.auto_away_active || self.windows.is_away() {
+            return;
+        }
+        if self.last_input.elapsed() >= Duration::from_secs(minutes * 60) {
+            self.auto_away_active = true;
+            let message = settings.ui.auto_away_message.clone();
+            self.send_quietly(Command::Away { message: Some(message) });
+        }
+    }
+
+    fn request_history(&mut self, target: String, before: Option<String>) {
+        if !self.caps.iter().any(|cap| cap == "draft/chathistory") {
+            return;
+        }
+        self.windows.set_loading(&target);
+        self.send_quietly(Command::History { target: target, before: before });
+    }
+
+    /// Labels a command so its replies can be told apart. The server drops
+    /// labels unless labeled-response is on, so they'd never be answered.
+    fn new_label(&mut self) -> Option<String> {
+        if !self.caps.iter().any(|cap| cap == "labeled-response") {
+            return None;
+        }
+        Some(self.windows.new_label())
+    }
+
+    fn update_typing(&mut self) {
+        let text = self.entry_line.text();
+        // Commands aren't messages, so they don't count as typing.
+        let empty = text.is_empty() || text.starts_with('/');
+        let target = self.windows.current_target().and_then(|target| target.id().name());
+        for (target, state) in self.typing.input(target, empty) {
+            self.send_quietly(Command::Typing { target: target, state: state });
+        }
+    }
+
     fn handle_line(&mut self, line: String) {
         if line.is_empty() {
             return;
@@ -87,11 +473,11 @@ impl Tui {
             self.handle_command(command, body);
             return;
         }
+        // The line is displayed once the irc layer echoes it back.
         if let Some(target) = self.windows.current_target() {
-            target.self_message(&line);
-            target.update_display();
             let target = String::from(target.id().name().expect("tui::handle_line target not found"));
-            self.irc_tx.send(Command::PrivMsg { target: target, message: line }).unwrap();
+            self.send(Command::PrivMsg { target: target, message: line });
+            self.typing.sent();
         } else {
             // TODO: Show error
         }
@@ -100,7 +486,7 @@ impl Tui {
     fn handle_command(&mut self, command: &str, body: &str) {
         let maybe_body = if body == "" { None } else { Some(body) };
         match command {
-            "join" => self.irc_tx.send(Command::Join { channel: String::from(body) }).unwrap(),
+            "join" => self.send(Command::Join { channel: String::from(body) }),
             "part" => {
                 use self::window::WindowId::*;
                 let channel = match *self.windows.current_window().id() {
@@ -108,11 +494,37 @@ impl Tui {
                     _ => None,
                 };
                 if let Some(channel) = channel {
-                    self.irc_tx.send(
+                    self.send(
                         Command::Part {
                             channel: channel.clone(),
                             message: maybe_body.map(String::from)
-                        }).unwrap();
+                        });
+                }
+            }
+            "connect" => self.connect(),
+            "reload" => self.reload(),
+            "names" => self.windows.show_names(),
+            "away" => {
+                let message = String::from(maybe_body.unwrap_or("Away"));
+                self.auto_away_active = false;
+                self.send(Command::Away { message: Some(message) });
+            }
+            "back" => {
+                self.auto_away_active = false;
+                self.send(Command::Away { message: None });
+            }
+            "whois" => {
+                let nick = String::from(body.trim());
+                let label = self.new_label();
+                self.send(Command::Whois { nick: nick, label: label });
+            }
+            "quote" => {
+                match body.parse::<Message>() {
+                    Ok(message) => {
+                        let label = self.new_label();
+                        self.send(Command::Raw { message: message, label: label });
+                    }
+                    Err(error) => self.windows.show_info(&format!("Couldn't send that: {}", error)),
                 }
             }
             "query" => {
@@ -120,15 +532,129 @@ impl Tui {
                 self.windows.query(body).ok();
             }
             "quit" => {
-                self.irc_tx.send(Command::Quit { message: maybe_body.map(String::from) }).unwrap();
+                self.send(Command::Quit { message: maybe_body.map(String::from) });
                 self.running = false;
             },
-            "win" | "w" => {
-                if let Ok(number) = body.parse::<usize>() {
-                    self.windows.change_to(number);
+            "window" | "win" | "w" => self.window_command(body),
+            "pane" => self.pane_command(body),
+            "theme" => self.theme_command(body.trim()),
+            _ => {} // TODO: Handle unknown command
+        }
+    }
+
+    /// Switches to a built-in theme until the config is next loaded.
+    fn theme_command(&mut self, name: &str) {
+        if name.is_empty() {
+            let current = self.settings.borrow().ui.theme.clone();
+            self.windows.show_info(&format!("Themes: {} (using {})", theme::names().join(", "), current));
+            return;
+        }
+        if !theme::exists(name) {
+            self.windows.show_info(&format!("There's no theme called {}. Themes: {}", name, theme::names().join(", ")));
+            return;
+        }
+        self.settings.borrow_mut().ui.theme = String::from(name);
+        self.windows.redraw();
+        self.windows.show_info(&format!("Using the {} theme. Set theme under [ui] to keep it.", name));
+    }
+
+    fn pane_command(&mut self, body: &str) {
+        let mut args = body.split_whitespace();
+        let subcommand = args.next().unwrap_or("");
+        let number = args.next().and_then(|x| x.parse::<usize>().ok());
+        let result = match subcommand {
+            "split" => {
+                self.windows.split(Direction::Horizontal);
+                Ok(())
+            }
+            "vsplit" => {
+                self.windows.split(Direction::Vertical);
+                Ok(())
+            }
+            "close" => self.windows.close_pane(number),
+            "only" => {
+                self.windows.only_pane();
+                Ok(())
+            }
+            "next" => {
+                self.windows.next_pane();
+                Ok(())
+            }
+            _ => match subcommand.parse::<usize>() {
+                Ok(number) => self.windows.focus_pane(number),
+                Err(_) => Err(String::from("Usage: /pane [N|split|vsplit|close [N]|only|next]")),
+            },
+        };
+        if let Err(message) = result {
+            self.windows.show_info(&message);
+        }
+    }
+
+    fn window_command(&mut self, body: &str) {
+        let mut args = body.split_whitespace();
+        let subcommand = args.next().unwrap_or("");
+        let args: Vec<&str> = args.collect();
+        let number = |arg: Option<&&str>| arg.and_then(|x| x.parse::<usize>().ok());
+        match subcommand {
+            "move" => {
+                let result = match number(args.get(0)) {
+                    Some(n) => self.windows.move_current(n),
+                    None => Err(String::from("Usage: /window move N")),
+                };
+                if let Err(message) = result {
+                    self.windows.show_info(&message);
+                }
+            }
+            "swap" => {
+                let result = match (number(args.get(0)), number(args.get(1))) {
+                    (Some(a), Some(b)) => self.windows.swap(a, b),
+                    _ => Err(String::from("Usage: /window swap A B")),
+                };
+                if let Err(message) = result {
+                    self.windows.show_info(&message);
+                }
+            }
+            "close" => {
+                let found = match args.get(0) {
+                    Some(query) => self.windows.find(query),
+                    None => Some(self.windows.current_window_number()),
+                };
+                match found {
+                    Some(0) => self.windows.show_info("The Status window can't be closed."),
+                    Some(n) => {
+                        if let Some(WindowId::Channel { name }) = self.windows.close(n) {
+                            self.send_quietly(Command::Part { channel: name, message: None });
+                        }
+                    }
+                    None => self.windows.show_info(&format!("No window matches {}.", args[0])),
+                }
+            }
+            "goto" => {
+                match args.get(0).and_then(|query| self.windows.find(query)) {
+                    Some(n) => self.windows.change_to(n),
+                    None => self.windows.show_info(&format!("No window matches {}.", args.get(0).unwrap_or(&""))),
+                }
+            }
+            "list" => {
+                for line in self.windows.list() {
+                    self.windows.show_info(&line);
+                }
+            }
+            _ => {
+                match body.trim().parse::<usize>() {
+                    Ok(n) => self.windows.change_to(n),
+                    Err(_) => self.windows.show_info("Usage: /window [N|move N|swap A B|close [name|N]|goto name|list]"),
                 }
             }
-            _ => {} // TODO: Handle unknown command
         }
     }
 }
+
+/// The name windows are saved under: the server given on the command line,
+/// or else the configured network.
+fn network_name(args: &Args, config: &ClientConfig) -> String {
+    match args.server {
+        Some(ref server) => server.clone(),
+        None => String::from(config.network_name()),
+    }
+}
@@ -0,0 +1,120 @@
+use std::fmt;
+
+use super::nicklist::Prefixes;
+
+/// Modes that are lists, such as bans, and so aren't part of the channel's
+/// mode string. Without ISUPPORT, these are the usual ones.
+const LIST_MODES: &'static str = "beI";
+/// Modes that take a parameter when set and when unset.
+const ALWAYS_PARAM: &'static str = "k";
+/// Modes that take a parameter only when set.
+const SET_PARAM: &'static str = "l";
+
+/// The modes set on a channel, as they'd be shown in `+ntk key`.
+#[derive(Default)]
+pub struct Modes {
+    modes: Vec<(char, Option<String>)>,
+}
+
+impl Modes {
+    pub fn new() -> Modes {
+        Modes::default()
+    }
+
+    /// Replaces the modes, from an RPL_CHANNELMODEIS.
+    pub fn set(&mut self, modes: &str, params: &[&str]) {
+        self.modes.clear();
+        self.apply(modes, params, &Prefixes::none());
+    }
+
+    /// Applies a change such as `+o-l nick`. Parameters are taken in order by
+    /// the modes that use them. Modes that give users prefixes aren't the
+    /// channel's, so they're returned instead, as (adding, mode, nick).
+    pub fn apply(&mut self, modes: &str, params: &[&str], prefixes: &Prefixes) -> Vec<(bool, char, String)> {
+        let mut params = params.iter();
+        let mut adding = true;
+        let mut prefix_changes = Vec::new();
+        for mode in modes.chars() {
+            match mode {
+                '+' => adding = true,
+                '-' => adding = false,
+                _ if prefixes.is_mode(mode) => {
+                    if let Some(nick) = params.next() {
+                        prefix_changes.push((adding, mode, String::from(*nick)));
+                    }
+                }
+                _ if LIST_MODES.contains(mode) => {
+                    params.next();
+                }
+                _ => {
+                    let takes_param = ALWAYS_PARAM.contains(mode) || (adding && SET_PARAM.contains(mode));
+                    let param = if takes_param { params.next().map(|x| String::from(*x)) } else { None };
+                    self.modes.retain(|&(x, _)| x != mode);
+                    if adding {
+                        self.modes.push((mode, param));
+                    }
+                }
+            }
+        }
+        prefix_changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.modes.is_empty()
+    }
+}
+
+impl fmt::Display for Modes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.modes.is_empty() {
+            return Ok(());
+        }
+        try!(write!(f, "+"));
+        for &(mode, _) in &self.modes {
+            try!(write!(f, "{}", mode));
+        }
+        for &(_, ref param) in &self.modes {
+            if let Some(ref param) = *param {
+                try!(write!(f, " {}", param));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Modes;
+    use tui::nicklist::Prefixes;
+
+    #[test]
+    fn applies_changes() {
+        let mut modes = Modes::new();
+        modes.apply("+ntl", &["10"], &Prefixes::new());
+        assert_eq!(modes.to_string(), "+ntl 10");
+        modes.apply("-l+k", &["secret"], &Prefixes::new());
+        assert_eq!(modes.to_string(), "+ntk secret");
+        // The key is given when unsetting it too.
+        modes.apply("-k", &["secret"], &Prefixes::new());
+        assert_eq!(modes.to_string(), "+nt");
+    }
+
+    #[test]
+    fn leaves_out_lists_and_returns_prefixes() {
+        let mut modes = Modes::new();
+        let changes = modes.apply("+bo-v+m", &["*!*@spam", "alice", "bob"], &Prefixes::new());
+        assert_eq!(modes.to_string(), "+m");
+        assert_eq!(changes, vec![(true, 'o', String::from("alice")), (false, 'v', String::from("bob"))]);
+    }
+
+    #[test]
+    fn set_replaces() {
+        let mut modes = Modes::new();
+        modes.apply("+nt", &[], &Prefixes::new());
+        modes.set("+s", &[]);
+        assert_eq!(modes.to_string(), "+s");
+        assert!(!modes.is_empty());
+        modes.set("", &[]);
+        assert!(modes.is_empty());
+    }
+}
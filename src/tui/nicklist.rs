@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+
+use irc::misc::{irc_equal, irc_lower};
+
+/// The channel modes that give users a prefix in the nick list, and the
+/// prefixes, highest first, from the PREFIX token of RPL_ISUPPORT.
+pub struct Prefixes {
+    modes: Vec<char>,
+    symbols: Vec<char>,
+}
+
+impl Prefixes {
+    /// The usual ones, until the server says which it has.
+    pub fn new() -> Prefixes {
+        Prefixes {
+            modes: "qaohv".chars().collect(),
+            symbols: "~&@%+".chars().collect(),
+        }
+    }
+
+    /// For user modes and RPL_CHANNELMODEIS, which have none.
+    pub fn none() -> Prefixes {
+        Prefixes {
+            modes: Vec::new(),
+            symbols: Vec::new(),
+        }
+    }
+
+    /// Parses a PREFIX value, such as `(ov)@+`.
+    pub fn parse(value: &str) -> Option<Prefixes> {
+        if !value.starts_with('(') {
+            return None;
+        }
+        let end = match value.find(')') {
+            Some(end) => end,
+            None => return None,
+        };
+        let modes: Vec<char> = value[1..end].chars().collect();
+        let symbols: Vec<char> = value[end + 1..].chars().collect();
+        if modes.len() != symbols.len() {
+            return None;
+        }
+        Some(Prefixes {
+            modes: modes,
+            symbols: symbols,
+        })
+    }
+
+    pub fn is_mode(&self, mode: char) -> bool {
+        self.modes.contains(&mode)
+    }
+
+    /// The prefix the mode gives, such as `@` for `o`.
+    pub fn symbol(&self, mode: char) -> Option<char> {
+        self.modes.iter().position(|&x| x == mode).map(|i| self.symbols[i])
+    }
+
+    /// Where the prefix ranks, 0 being the highest.
+    fn rank(&self, symbol: char) -> usize {
+        self.symbols.iter().position(|&x| x == symbol).unwrap_or(self.symbols.len())
+    }
+}
+
+pub struct Nick {
+    pub prefix: String,
+    pub name: String,
+}
+
+/// The users of a channel, in the order the server listed them.
+pub struct NickList {
+    nicks: Vec<Nick>,
+}
+
+impl NickList {
+    pub fn new() -> NickList {
+        NickList {
+            nicks: Vec::new(),
+        }
+    }
+
+    /// Adds a nick, which may carry mode prefixes as in a NAMES reply.
+    pub fn add(&mut self, nick: &str, prefixes: &Prefixes) {
+        let name = nick.trim_left_matches(&prefixes.symbols[..]);
+        let prefix = &nick[..nick.len() - name.len()];
+        // userhost-in-names sends nick!user@host.
+        let name = name.split('!').next().unwrap_or(name);
+        match self.nicks.iter().position(|x| irc_equal(&x.name, name)) {
+            Some(i) => self.nicks[i].prefix = String::from(prefix),
+            None => self.nicks.push(Nick { prefix: String::from(prefix), name: String::from(name) }),
+        }
+    }
+
+    pub fn remove(&mut self, nick: &str) -> bool {
+        let len = self.nicks.len();
+        self.nicks.retain(|x| !irc_equal(&x.name, nick));
+        self.nicks.len() != len
+    }
+
+    pub fn rename(&mut self, old: &str, new: &str) -> bool {
+        match self.nicks.iter_mut().find(|x| irc_equal(&x.name, old)) {
+            Some(nick) => {
+                nick.name = String::from(new);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The highest of the nick's mode prefixes, such as `@` for an operator.
+    pub fn prefix(&self, nick: &str) -> Option<&str> {
+        self.nicks.iter()
+            .find(|x| irc_equal(&x.name, nick))
+            .and_then(|x| x.prefix.chars().next().map(|ch| &x.prefix[..ch.len_utf8()]))
+    }
+
+    /// Gives the nick a prefix or takes it away, after a MODE such as
+    /// `+o nick`. Prefixes are kept highest first.
+    pub fn set_prefix(&mut self, nick: &str, symbol: char, adding: bool, prefixes: &Prefixes) {
+        if let Some(x) = self.nicks.iter_mut().find(|x| irc_equal(&x.name, nick)) {
+            let mut symbols: Vec<char> = x.prefix.chars().filter(|&ch| ch != symbol).collect();
+            if adding {
+                symbols.push(symbol);
+            }
+            symbols.sort_by_key(|&ch| prefixes.rank(ch));
+            x.prefix = symbols.into_iter().collect();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.nicks.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.nicks.len()
+    }
+
+    pub fn iter(&self) -> ::std::slice::Iter<Nick> {
+        self.nicks.iter()
+    }
+}
+
+#[derive(Default)]
+struct User {
+    account: Option<String>,
+    away: Option<String>,
+    /// `user@host`, from the prefix of their messages or from WHOIS.
+    host: Option<String>,
+}
+
+/// What we know about users regardless of channel: their services account,
+/// from account-tag and account-notify, their away message, from away-notify
+/// and RPL_AWAY, and their user@host.
+pub struct Users {
+    users: HashMap<String, User>,
+}
+
+impl Users {
+    pub fn new() -> Users {
+        Users {
+            users: HashMap::new(),
+        }
+    }
+
+    pub fn account(&self, nick: &str) -> Option<&str> {
+        self.users.get(&irc_lower(nick)).and_then(|x| x.account.as_ref()).map(|x| &x[..])
+    }
+
+    /// An account of `*` means the user has logged out.
+    pub fn set_account(&mut self, nick: &str, account: &str) {
+        let user = self.users.entry(irc_lower(nick)).or_insert_with(User::default);
+        user.account = if account == "*" { None } else { Some(String::from(account)) };
+    }
+
+    pub fn away(&self, nick: &str) -> Option<&str> {
+        self.users.get(&irc_lower(nick)).and_then(|x| x.away.as_ref()).map(|x| &x[..])
+    }
+
+    pub fn set_away(&mut self, nick: &str, message: Option<&str>) {
+        let user = self.users.entry(irc_lower(nick)).or_insert_with(User::default);
+        user.away = message.map(String::from);
+    }
+
+    pub fn host(&self, nick: &str) -> Option<&str> {
+        self.users.get(&irc_lower(nick)).and_then(|x| x.host.as_ref()).map(|x| &x[..])
+    }
+
+    pub fn set_host(&mut self, nick: &str, host: &str) {
+        let user = self.users.entry(irc_lower(nick)).or_insert_with(User::default);
+        user.host = Some(String::from(host));
+    }
+
+    pub fn remove(&mut self, nick: &str) {
+        self.users.remove(&irc_lower(nick));
+    }
+
+    pub fn rename(&mut self, old: &str, new: &str) {
+        if let Some(user) = self.users.remove(&irc_lower(old)) {
+            self.users.insert(irc_lower(new), user);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NickList, Prefixes};
+
+    fn prefix_of(nicks: &NickList, name: &str) -> String {
+        nicks.iter().find(|x| x.name == name).map(|x| x.prefix.clone()).unwrap()
+    }
+
+    #[test]
+    fn parses_prefix_tokens() {
+        let prefixes = Prefixes::parse("(ohv)@%+").unwrap();
+        assert_eq!(prefixes.symbol('o'), Some('@'));
+        assert_eq!(prefixes.symbol('v'), Some('+'));
+        assert_eq!(prefixes.symbol('q'), None);
+        assert!(Prefixes::parse("@+").is_none());
+        assert!(Prefixes::parse("(ov)@").is_none());
+    }
+
+    #[test]
+    fn names_replies_carry_prefixes() {
+        let prefixes = Prefixes::new();
+        let mut nicks = NickList::new();
+        nicks.add("@+alice!a@example.org", &prefixes);
+        nicks.add("bob", &prefixes);
+        assert_eq!(prefix_of(&nicks, "alice"), "@+");
+        assert_eq!(nicks.prefix("Alice"), Some("@"));
+        assert_eq!(nicks.prefix("bob"), None);
+    }
+
+    #[test]
+    fn modes_change_prefixes_in_order() {
+        let prefixes = Prefixes::new();
+        let mut nicks = NickList::new();
+        nicks.add("bob", &prefixes);
+        nicks.set_prefix("bob", '+', true, &prefixes);
+        nicks.set_prefix("BOB", '@', true, &prefixes);
+        assert_eq!(prefix_of(&nicks, "bob"), "@+");
+        // Setting it twice doesn't repeat it.
+        nicks.set_prefix("bob", '+', true, &prefixes);
+        assert_eq!(prefix_of(&nicks, "bob"), "@+");
+        nicks.set_prefix("bob", '@', false, &prefixes);
+        assert_eq!(nicks.prefix("bob"), Some("+"));
+    }
+
+    #[test]
+    fn server_order_wins() {
+        let prefixes = Prefixes::parse("(vo)+@").unwrap();
+        let mut nicks = NickList::new();
+        nicks.add("carol", &prefixes);
+        nicks.set_prefix("carol", '@', true, &prefixes);
+        nicks.set_prefix("carol", '+', true, &prefixes);
+        assert_eq!(prefix_of(&nicks, "carol"), "+@");
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+
+use super::layout::Rect;
+use super::window::WindowId;
+
+pub type PaneId = usize;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Direction {
+    /// One pane above the other.
+    Horizontal,
+    /// Panes side by side, with a line between them.
+    Vertical,
+}
+
+enum Node {
+    Pane(PaneId),
+    Split(Direction, Vec<Node>),
+}
+
+impl Node {
+    fn contains(&self, pane: PaneId) -> bool {
+        match *self {
+            Node::Pane(id) => id == pane,
+            Node::Split(_, ref children) => children.iter().any(|child| child.contains(pane)),
+        }
+    }
+
+    fn panes(&self, panes: &mut Vec<PaneId>) {
+        match *self {
+            Node::Pane(id) => panes.push(id),
+            Node::Split(_, ref children) => {
+                for child in children {
+                    child.panes(panes);
+                }
+            }
+        }
+    }
+
+    /// Splits a pane, putting the new one after it. A split in the same
+    /// direction as the one the pane is already in just adds a sibling.
+    fn split(&mut self, pane: PaneId, direction: Direction, new: PaneId) -> bool {
+        if let Node::Pane(id) = *self {
+            if id != pane {
+                return false;
+            }
+            *self = Node::Split(direction, vec![Node::Pane(id), Node::Pane(new)]);
+            return true;
+        }
+        let index = match *self {
+            Node::Pane(_) => return false,
+            Node::Split(split_direction, ref mut children) => {
+                let index = children.iter().position(|child| match *child {
+                    Node::Pane(id) => id == pane,
+                    _ => false,
+                });
+                match index {
+                    Some(index) if split_direction == direction => index,
+                    _ => return children.iter_mut().any(|child| child.split(pane, direction, new)),
+                }
+            }
+        };
+        if let Node::Split(_, ref mut children) = *self {
+            children.insert(index + 1, Node::Pane(new));
+        }
+        true
+    }
+
+    /// Removes a pane. Splits left with one pane are replaced by it.
+    fn remove(&mut self, pane: PaneId) {
+        let only_child = match *self {
+            Node::Pane(_) => return,
+            Node::Split(_, ref mut children) => {
+                children.retain(|child| match *child {
+                    Node::Pane(id) => id != pane,
+                    _ => true,
+                });
+                for child in children.iter_mut() {
+                    child.remove(pane);
+                }
+                if children.len() == 1 { children.pop() } else { None }
+            }
+        };
+        if let Some(child) = only_child {
+            *self = child;
+        }
+    }
+
+    fn rects(&self, area: Rect, rects: &mut Vec<(PaneId, Rect)>) {
+        let (direction, children) = match *self {
+            Node::Pane(id) => {
+                rects.push((id, area));
+                return;
+            }
+            Node::Split(direction, ref children) => (direction, children),
+        };
+        let count = children.len() as u16;
+        // Side by side panes have a column between them for the line.
+        let (total, gap) = match direction {
+            Direction::Horizontal => (area.height, 0),
+            Direction::Vertical => (area.width.saturating_sub(count - 1), 1),
+        };
+        let mut offset = 0;
+        for (i, child) in children.iter().enumerate() {
+            let i = i as u16;
+            // The first panes get the rows or columns that don't divide evenly.
+            let size = total / count + if i < total % count { 1 } else { 0 };
+            let rect = match direction {
+                Direction::Horizontal => Rect { y: area.y + offset, height: size, ..area },
+                Direction::Vertical => Rect { x: area.x + offset, width: size, ..area },
+            };
+            child.rects(rect, rects);
+            offset += size + gap;
+        }
+    }
+}
+
+/// How the window area is split into panes, and which window each one shows.
+/// The focused pane shows the current window, so its entry is only kept up
+/// to date when the focus moves away.
+pub struct Panes {
+    root: Node,
+    focused: PaneId,
+    next_id: PaneId,
+    windows: HashMap<PaneId, WindowId>,
+}
+
+impl Panes {
+    pub fn new() -> Panes {
+        Panes {
+            root: Node::Pane(0),
+            focused: 0,
+            next_id: 1,
+            windows: HashMap::new(),
+        }
+    }
+
+    /// The panes in order, top to bottom and left to right.
+    pub fn ids(&self) -> Vec<PaneId> {
+        let mut panes = Vec::new();
+        self.root.panes(&mut panes);
+        panes
+    }
+
+    pub fn count(&self) -> usize {
+        self.ids().len()
+    }
+
+    pub fn focused(&self) -> PaneId {
+        self.focused
+    }
+
+    /// Panes are numbered from 1 in the order of `ids`.
+    pub fn number_of(&self, pane: PaneId) -> usize {
+        self.ids().iter().position(|&id| id == pane).map(|i| i + 1).unwrap_or(0)
+    }
+
+    pub fn by_number(&self, number: usize) -> Option<PaneId> {
+        if number == 0 {
+            return None;
+        }
+        self.ids().get(number - 1).cloned()
+    }
+
+    /// The window a pane other than the focused one shows.
+    pub fn window(&self, pane: PaneId) -> WindowId {
+        self.windows.get(&pane).cloned().unwrap_or(WindowId::Status)
+    }
+
+    /// Splits the focused pane. The old pane keeps showing `current`, and
+    /// the new one gets the focus.
+    pub fn split(&mut self, direction: Direction, current: WindowId) {
+        let new = self.next_id;
+        self.next_id += 1;
+        let focused = self.focused;
+        self.root.split(focused, direction, new);
+        self.windows.insert(focused, current);
+        self.focused = new;
+    }
+
+    /// Closes a pane, unless it's the last one. Returns the window to show
+    /// if the focused pane was closed and the focus moved.
+    pub fn close(&mut self, pane: PaneId) -> Result<Option<WindowId>, String> {
+        if self.count() == 1 {
+            return Err(String::from("The last pane can't be closed."));
+        }
+        if !self.root.contains(pane) {
+            return Err(String::from("There's no such pane."));
+        }
+        let ids = self.ids();
+        self.root.remove(pane);
+        self.windows.remove(&pane);
+        if pane != self.focused {
+            return Ok(None);
+        }
+        // Focus the pane before the closed one, or after it if it was first.
+        let index = ids.iter().position(|&id| id == pane).unwrap_or(0);
+        self.focused = if index > 0 { ids[index - 1] } else { ids[1] };
+        Ok(Some(self.window(self.focused)))
+    }
+
+    /// Moves the focus to another pane, remembering that the one it leaves
+    /// shows `current`. Returns the window the focused pane shows.
+    pub fn focus(&mut self, pane: PaneId, current: WindowId) -> WindowId {
+        let focused = self.focused;
+        self.windows.insert(focused, current);
+        self.focused = pane;
+        self.window(pane)
+    }
+
+    /// Panes showing a window that's been closed show the Status window
+    /// instead.
+    pub fn window_closed(&mut self, closed: &WindowId) {
+        for window in self.windows.values_mut() {
+            if *window == *closed {
+                *window = WindowId::Status;
+            }
+        }
+    }
+
+    pub fn rects(&self, area: Rect) -> Vec<(PaneId, Rect)> {
+        let mut rects = Vec::new();
+        self.root.rects(area, &mut rects);
+        rects
+    }
+}
@@ -0,0 +1,76 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use toml;
+
+use config;
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Channel,
+    Query,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SavedWindow {
+    pub network: String,
+    pub kind: Kind,
+    pub name: String,
+    /// When the newest message that had been on screen was sent, in
+    /// milliseconds since the epoch.
+    pub read_until_ms: Option<i64>,
+}
+
+/// The windows open when the client last ran. They're kept in the order they
+/// were numbered in, so that the numbers stay the same.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Session {
+    #[serde(default)]
+    pub windows: Vec<SavedWindow>,
+}
+
+impl Session {
+    /// A missing or unreadable session just means starting afresh.
+    pub fn load() -> Session {
+        let mut source = String::new();
+        let read = File::open(path()).and_then(|mut file| file.read_to_string(&mut source));
+        if read.is_err() {
+            return Session::default();
+        }
+        toml::from_str(&source).unwrap_or_default()
+    }
+
+    /// The saved windows of one network.
+    pub fn windows<'a>(&'a self, network: &'a str) -> Box<Iterator<Item = &'a SavedWindow> + 'a> {
+        Box::new(self.windows.iter().filter(move |window| window.network == network))
+    }
+
+    /// Replaces the saved windows of a network, leaving other networks'
+    /// alone.
+    pub fn set_windows(&mut self, network: &str, windows: Vec<SavedWindow>) {
+        self.windows.retain(|window| window.network != network);
+        self.windows.extend(windows);
+    }
+
+    /// Writes to a temporary file first, so that a crash while saving doesn't
+    /// lose the previous session.
+    pub fn save(&self) -> io::Result<()> {
+        let path = path();
+        if let Some(directory) = path.parent() {
+            try!(fs::create_dir_all(directory));
+        }
+        let text = try!(toml::to_string(self).map_err(|error| io::Error::new(io::ErrorKind::Other, error)));
+        let temporary = path.with_extension("toml.tmp");
+        {
+            let mut file = try!(File::create(&temporary));
+            try!(file.write_all(text.as_bytes()));
+        }
+        fs::rename(temporary, path)
+    }
+}
+
+fn path() -> PathBuf {
+    config::data_dir().join("session.toml")
+}
@@ -0,0 +1,173 @@
+use std::cell::RefCell;
+use std::cmp;
+
+use termion::event::Key;
+use termion::{cursor, style};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use super::Settings;
+use super::layout::SharedLayout;
+use super::theme::{Style, Theme};
+use super::window::Windows;
+
+/// Lists the windows down the left of the screen, under the network they're
+/// on, with how many unread messages and highlights they have.
+pub struct Sidebar {
+    settings: Settings,
+    layout: SharedLayout,
+    /// The window picked out while choosing one with the keyboard.
+    cursor: Option<usize>,
+    /// The window number on each row as last drawn, for mouse clicks.
+    rows: RefCell<Vec<Option<usize>>>,
+}
+
+impl Sidebar {
+    pub fn new(settings: Settings, layout: SharedLayout) -> Sidebar {
+        Sidebar {
+            settings: settings,
+            layout: layout,
+            cursor: None,
+            rows: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// The width to draw at, or 0 if it's hidden.
+    pub fn width(&self) -> u16 {
+        let settings = self.settings.borrow();
+        let sidebar = &settings.ui.sidebar;
+        let (cols, _) = self.layout.size();
+        // The panes need some room too.
+        if !sidebar.enabled || cols < sidebar.auto_hide_below || sidebar.width + 1 >= cols {
+            0
+        } else {
+            sidebar.width
+        }
+    }
+
+    pub fn draw(&self, windows: &Windows, network: &str) {
+        let rect = self.layout.sidebar_rect();
+        let mut rows = self.rows.borrow_mut();
+        rows.clear();
+        if rect.width == 0 {
+            return;
+        }
+        let theme = Theme::new(&self.settings.borrow().ui);
+        let depth = theme.depth();
+        let base = theme.style("sidebar");
+        let width = rect.width as usize;
+        let current = windows.current_window_number();
+
+        let header = Style { bold: true, ..base };
+        let mut lines = vec![(None, format!("{}{}{}", header.ansi(depth), pad(network, width), style::Reset))];
+        for (number, window) in windows.iter().enumerate() {
+            let marker = if self.cursor == Some(number) { '>' } else { ' ' };
+            let prefix = format!("{}{:>2} ", marker, number);
+            let mut counts = Vec::new();
+            if window.unread() > 0 {
+                counts.push((window.unread().to_string(), theme.style("activity")));
+            }
+            if window.highlights() > 0 {
+                counts.push((window.highlights().to_string(), theme.style("highlight")));
+            }
+            let mut counts_width: usize = counts.iter().map(|&(ref count, _)| count.len() + 1).sum();
+            // When it's narrow, the counts go before any of the name does,
+            // the unread one first, and then the row is cut.
+            while !counts.is_empty() && prefix.len() + counts_width >= width {
+                let (count, _) = counts.remove(0);
+                counts_width -= count.len() + 1;
+            }
+            let prefix_width = cmp::min(prefix.len(), width);
+            let name_width = width - prefix_width - counts_width;
+            let line_style = if number == current { Style { reverse: true, ..base } } else { base };
+            let mut line = format!("{}{}{}", line_style.ansi(depth), &prefix[..prefix_width], pad(window.name(), name_width));
+            for (count, count_style) in counts {
+                line.push_str(&format!(" {}{}{}", count_style.over(&line_style).ansi(depth), count, line_style.ansi(depth)));
+            }
+            line.push_str(&style::Reset.to_string());
+            lines.push((Some(number), line));
+        }
+
+        // Scroll to keep the chosen window, or else the current one, in view.
+        let height = rect.height as usize;
+        let shown = self.cursor.unwrap_or(current) + 1;
+        let offset = cmp::min((shown + 1).saturating_sub(height), lines.len().saturating_sub(height));
+        let blank = format!("{}{}{}", base.ansi(depth), " ".repeat(width), style::Reset);
+        for row in 0..height {
+            let y = rect.y + row as u16;
+            match lines.get(offset + row) {
+                Some(&(number, ref line)) => {
+                    print!("{}{}", cursor::Goto(rect.x, y), line);
+                    rows.push(number);
+                }
+                None => {
+                    print!("{}{}", cursor::Goto(rect.x, y), blank);
+                    rows.push(None);
+                }
+            }
+            print!("{}│", cursor::Goto(rect.x + rect.width, y));
+        }
+    }
+
+    /// The window on the row that was clicked.
+    pub fn click(&self, x: u16, y: u16) -> Option<usize> {
+        let rect = self.layout.sidebar_rect();
+        if x < rect.x || x >= rect.x + rect.width || y < rect.y {
+            return None;
+        }
+        self.rows.borrow().get((y - rect.y) as usize).cloned().unwrap_or(None)
+    }
+
+    pub fn is_selecting(&self) -> bool {
+        self.cursor.is_some()
+    }
+
+    pub fn start_selecting(&mut self, current: usize) {
+        self.cursor = Some(current);
+    }
+
+    /// Moves the choice with the arrows, or `j` and `k`, out of `count`
+    /// windows. Returns the window chosen with Enter. Escape gives up.
+    pub fn select_key(&mut self, key: Key, count: usize) -> Option<usize> {
+        let cursor = match self.cursor {
+            Some(cursor) => cursor,
+            None => return None,
+        };
+        let last = count.saturating_sub(1);
+        self.cursor = match key {
+            Key::Up | Key::Ctrl('p') | Key::Char('k') => Some(cursor.saturating_sub(1)),
+            Key::Down | Key::Ctrl('n') | Key::Char('j') => Some(cmp::min(cursor + 1, last)),
+            Key::Home => Some(0),
+            Key::End => Some(last),
+            Key::Char('\n') => {
+                self.cursor = None;
+                return Some(cursor);
+            }
+            Key::Esc | Key::Ctrl('c') | Key::Ctrl('g') => None,
+            _ => Some(cursor),
+        };
+        None
+    }
+}
+
+/// Cuts text down to `width` columns, with `…` where it was cut, and pads it
+/// out to `width` with spaces.
+fn pad(text: &str, width: usize) -> String {
+    if UnicodeWidthStr::width(text) <= width {
+        return format!("{}{}", text, " ".repeat(width - UnicodeWidthStr::width(text)));
+    }
+    let mut cut = String::new();
+    let mut used = 0;
+    for ch in text.chars() {
+        let ch_width = UnicodeWidthChar::width(ch).unwrap_or(0);
+        if used + ch_width + 1 > width {
+            break;
+        }
+        cut.push(ch);
+        used += ch_width;
+    }
+    if width > 0 {
+        cut.push('…');
+        used += 1;
+    }
+    format!("{}{}", cut, " ".repeat(width - used))
+}
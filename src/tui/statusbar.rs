@@ -1,41 +1,261 @@
-use super::window::Windows;
+use std::time::Duration;
 
-use termion::{self, clear, color, cursor};
+use chrono::Local;
+use termion::{clear, cursor, style};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use super::window::{ActivityLevel, Windows};
+use super::Settings;
+use super::layout::SharedLayout;
+use super::theme::{ColorDepth, Style, Theme};
+
+/// The items that can go in a status bar template, as `{name}`.
+pub const ITEMS: &'static [&'static str] = &[
+    "time", "nick", "lag", "window", "network", "channel_modes", "away",
+    "activity", "queue", "scroll", "typing",
+];
+
+/// What the status bar shows that isn't kept in the windows.
+pub struct StatusInfo {
+    pub network: String,
+    /// `None` until it's been measured.
+    pub lag: Option<Duration>,
+    /// Commands waiting to be sent to the server.
+    pub queued: usize,
+}
+
+enum Piece<'a> {
+    Text(&'a str),
+    Item(&'a str),
+}
+
+/// Text to print, and the style to print it in over the bar's.
+type Segment = (String, Style);
+
+fn parse(template: &str) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            pieces.push(Piece::Text(&rest[..start]));
+        }
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => return Err(format!("unclosed `{{` in status bar template `{}`", template)),
+        };
+        pieces.push(Piece::Item(&rest[start + 1..end]));
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        pieces.push(Piece::Text(rest));
+    }
+    Ok(pieces)
+}
+
+/// Checks that a template only uses known items.
+pub fn check_template(template: &str) -> Result<(), String> {
+    for piece in try!(parse(template)) {
+        if let Piece::Item(name) = piece {
+            if !ITEMS.contains(&name) {
+                return Err(format!("unknown status bar item `{{{}}}`", name));
+            }
+        }
+    }
+    Ok(())
+}
 
 pub struct StatusBar {
+    settings: Settings,
+    layout: SharedLayout,
 }
 
 impl StatusBar {
-    pub fn new() -> StatusBar {
+    pub fn new(settings: Settings, layout: SharedLayout) -> StatusBar {
         StatusBar {
+            settings: settings,
+            layout: layout,
+        }
+    }
+
+    /// How many rows it takes, from the config.
+    pub fn rows(&self) -> u16 {
+        self.settings.borrow().ui.statusbar.lines.len() as u16
+    }
+
+    pub fn draw(&self, windows: &Windows, info: &StatusInfo) {
+        print!("{}", cursor::Hide);
+        let settings = self.settings.borrow();
+        let theme = Theme::new(&settings.ui);
+        let base = theme.style("statusbar");
+        let (cols, _) = self.layout.size();
+        let cols = cols as usize;
+        let first_row = self.layout.status_row();
+        for (i, line) in settings.ui.statusbar.lines.iter().enumerate() {
+            // Clearing the line fills it with the bar's background.
+            print!("{}{}{}", cursor::Goto(1, first_row + i as u16), base.ansi(theme.depth()), clear::CurrentLine);
+            let (left, left_width) = truncate(self.render(&line.left, windows, info, &theme), cols);
+            print_segments(&left, &base, theme.depth());
+            let mut right = self.render(&line.right, windows, info, &theme);
+            trim_start(&mut right);
+            let right_width = segments_width(&right);
+            // The right side is left out if it doesn't fit beside the left.
+            if right_width > 0 && left_width + right_width < cols {
+                print!("{}", cursor::Goto((cols - right_width + 1) as u16, first_row + i as u16));
+                print_segments(&right, &base, theme.depth());
+            }
+            print!("{}", style::Reset);
+        }
+    }
+
+    fn render(&self, template: &str, windows: &Windows, info: &StatusInfo, theme: &Theme) -> Vec<Segment> {
+        let pieces = match parse(template) {
+            Ok(pieces) => pieces,
+            Err(_) => return Vec::new(),
+        };
+        let mut segments = Vec::new();
+        let mut skip_space = false;
+        for piece in pieces {
+            match piece {
+                Piece::Text(text) => {
+                    let text = if skip_space && text.starts_with(' ') { &text[1..] } else { text };
+                    skip_space = false;
+                    if !text.is_empty() {
+                        segments.push((String::from(text), Style::default()));
+                    }
+                }
+                Piece::Item(name) => {
+                    let item = self.item(name, windows, info, theme);
+                    skip_space = item.is_empty();
+                    segments.extend(item);
+                }
+            }
         }
+        segments
     }
 
-    pub fn draw(&self, windows: &Windows) {
-        let (_, rows) = termion::terminal_size().unwrap();
-        print!("{}{}", cursor::Hide, cursor::Goto(1, rows - 1));
-        print!("{}", clear::CurrentLine);
-        let cur_win_number = windows.current_window_number();
-        let cur_win_name = windows.current_window().name();
-        let highest_win = windows.highest_window_index();
-        print!("[{}: {}] Highest window: {} [Act:",
-            cur_win_number,
-            cur_win_name,
-            highest_win);
-        for (index, activity) in windows.activity() {
-            use super::window::ActivityLevel::*;
-            match activity {
-                Inactive => continue,
-                Active => {
-                    print!(" {}", index);
+    fn item(&self, name: &str, windows: &Windows, info: &StatusInfo, theme: &Theme) -> Vec<Segment> {
+        let settings = self.settings.borrow();
+        let item_style = settings.ui.statusbar.colors.get(name)
+            .and_then(|spec| Style::parse(spec).ok())
+            .unwrap_or_default();
+        let window = windows.current_window();
+        let text = match name {
+            "time" => Local::now().format(&settings.ui.statusbar.time_format).to_string(),
+            "nick" => {
+                if windows.user_modes().is_empty() {
+                    String::from(windows.nick())
+                } else {
+                    format!("{}({})", windows.nick(), windows.user_modes())
                 }
-                Hilight => {
-                    print!("{}", color::Fg(color::Red));
-                    print!(" {}", index);
-                    print!("{}", color::Fg(color::Reset));
+            }
+            "lag" => match info.lag {
+                Some(lag) => {
+                    let text = format!("Lag: {}.{}s", lag.as_secs(), lag.subsec_nanos() / 100_000_000);
+                    let statusbar = &settings.ui.statusbar;
+                    let style = if lag >= Duration::from_secs(statusbar.lag_alert) {
+                        theme.style("lag_alert")
+                    } else if lag >= Duration::from_secs(statusbar.lag_warning) {
+                        theme.style("lag_warning")
+                    } else {
+                        item_style
+                    };
+                    return vec![(text, style)];
                 }
+                None => String::new(),
+            },
+            "window" => format!("{}: {}", windows.current_window_number(), window.name()),
+            "network" => info.network.clone(),
+            "channel_modes" => window.modes().to_string(),
+            "away" => if windows.is_away() { String::from("[Away]") } else { String::new() },
+            "activity" => return self.activity(windows, item_style, theme),
+            "queue" => if info.queued > 0 { format!("Queued: {}", info.queued) } else { String::new() },
+            "scroll" => if window.scrolled() > 0 { format!("-- More ({}) --", window.scrolled()) } else { String::new() },
+            "typing" => {
+                let typing = window.typing_nicks();
+                match typing.len() {
+                    0 => String::new(),
+                    1 => format!("{} is typing…", typing[0]),
+                    _ => format!("{} are typing…", typing.join(", ")),
+                }
+            }
+            _ => String::new(),
+        };
+        if text.is_empty() { Vec::new() } else { vec![(text, item_style)] }
+    }
+
+    /// The windows with activity, shown as window:unread with highlights in
+    /// brackets, each in the style for how active it is.
+    fn activity(&self, windows: &Windows, item_style: Style, theme: &Theme) -> Vec<Segment> {
+        let mut segments = vec![(String::from("[Act:"), item_style)];
+        for (index, window) in windows.iter().enumerate() {
+            let style = match window.activity() {
+                ActivityLevel::Inactive => continue,
+                ActivityLevel::Active => theme.style("activity"),
+                ActivityLevel::Hilight => theme.style("highlight"),
+            };
+            let mut item = format!(" {}", index);
+            if window.unread() > 0 {
+                item.push_str(&format!(":{}", window.unread()));
+            }
+            if window.highlights() > 0 {
+                item.push_str(&format!("({})", window.highlights()));
+            }
+            segments.push((item, style));
+        }
+        if segments.len() == 1 {
+            return Vec::new();
+        }
+        segments.push((String::from("]"), item_style));
+        segments
+    }
+}
+
+fn segments_width(segments: &[Segment]) -> usize {
+    segments.iter().map(|&(ref text, _)| UnicodeWidthStr::width(&text[..])).sum()
+}
+
+/// Cuts segments down to `width` columns. Returns them with the columns
+/// they take.
+fn truncate(segments: Vec<Segment>, width: usize) -> (Vec<Segment>, usize) {
+    let mut cut = Vec::new();
+    let mut used = 0;
+    for (text, color) in segments {
+        let mut kept = String::new();
+        for ch in text.chars() {
+            let ch_width = UnicodeWidthChar::width(ch).unwrap_or(0);
+            if used + ch_width > width {
+                break;
             }
+            kept.push(ch);
+            used += ch_width;
+        }
+        let full = kept.len() == text.len();
+        cut.push((kept, color));
+        if !full {
+            break;
+        }
+    }
+    (cut, used)
+}
+
+/// Removes the spaces left at the start when the first items are empty.
+fn trim_start(segments: &mut Vec<Segment>) {
+    while !segments.is_empty() {
+        let trimmed = String::from(segments[0].0.trim_left());
+        if !trimmed.is_empty() {
+            segments[0].0 = trimmed;
+            return;
+        }
+        segments.remove(0);
+    }
+}
+
+fn print_segments(segments: &[Segment], base: &Style, depth: ColorDepth) {
+    for &(ref text, ref style) in segments {
+        if style.is_plain() {
+            print!("{}", text);
+        } else {
+            print!("{}{}{}", style.over(base).ansi(depth), text, base.ansi(depth));
         }
-        print!("]");
     }
 }
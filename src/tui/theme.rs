@@ -0,0 +1,478 @@
+use std::collections::HashMap;
+use std::env;
+
+use termion::{color, style};
+
+use config::{color_index, Ui};
+use irc::misc::irc_lower;
+
+/// The parts of the screen that can be styled, under `[ui.styles]`.
+pub const ELEMENTS: &'static [&'static str] = &[
+    "timestamp", "message", "own_nick", "nick", "join_part", "error",
+    "highlight", "activity", "marker", "statusbar", "topicbar", "sidebar",
+    "entry_line", "lag_warning", "lag_alert",
+];
+
+/// The built-in themes, the background they're meant for, and the styles
+/// they give elements. Elements a theme leaves out are drawn plainly.
+const THEMES: &'static [(&'static str, &'static str, &'static [(&'static str, &'static str)])] = &[
+    ("default", "black", &[
+        ("timestamp", "light_black"),
+        ("own_nick", "bold"),
+        ("nick", "bold"),
+        ("join_part", "light_black"),
+        ("error", "red"),
+        ("highlight", "red"),
+        ("marker", "light_black"),
+        ("lag_warning", "yellow"),
+        ("lag_alert", "red"),
+    ]),
+    ("light", "white", &[
+        ("timestamp", "244"),
+        ("own_nick", "bold 18"),
+        ("nick", "bold"),
+        ("join_part", "244"),
+        ("error", "124"),
+        ("highlight", "bold 124"),
+        ("activity", "22"),
+        ("marker", "244"),
+        ("statusbar", "black on 252"),
+        ("topicbar", "bold black on 252"),
+        ("sidebar", "black on 255"),
+        ("lag_warning", "130"),
+        ("lag_alert", "124"),
+    ]),
+    ("solarized", "#002b36", &[
+        ("timestamp", "#586e75"),
+        ("message", "#839496"),
+        ("own_nick", "bold #268bd2"),
+        ("nick", "bold #93a1a1"),
+        ("join_part", "#586e75"),
+        ("error", "#dc322f"),
+        ("highlight", "bold #cb4b16"),
+        ("activity", "#2aa198"),
+        ("marker", "#b58900"),
+        ("statusbar", "#93a1a1 on #073642"),
+        ("topicbar", "bold #93a1a1 on #073642"),
+        ("sidebar", "#839496 on #002b36"),
+        ("entry_line", "#93a1a1"),
+        ("lag_warning", "#b58900"),
+        ("lag_alert", "#dc322f"),
+    ]),
+    ("mono", "black", &[
+        ("own_nick", "bold"),
+        ("nick", "bold"),
+        ("error", "bold"),
+        ("highlight", "bold underline"),
+        ("activity", "underline"),
+        ("statusbar", "reverse"),
+        ("topicbar", "reverse"),
+        ("lag_warning", "bold"),
+        ("lag_alert", "bold reverse"),
+    ]),
+];
+
+/// The names of the built-in themes.
+pub fn names() -> Vec<&'static str> {
+    THEMES.iter().map(|&(name, _, _)| name).collect()
+}
+
+pub fn exists(name: &str) -> bool {
+    THEMES.iter().any(|&(x, _, _)| x == name)
+}
+
+/// How many colours the terminal can show.
+#[derive(Copy, Clone, PartialEq)]
+pub enum ColorDepth {
+    Basic,
+    Palette,
+    TrueColor,
+}
+
+impl ColorDepth {
+    /// `16`, `256` or `truecolor`, or anything else to guess from the
+    /// environment.
+    pub fn from_setting(setting: &str) -> ColorDepth {
+        match setting {
+            "16" => ColorDepth::Basic,
+            "256" => ColorDepth::Palette,
+            "truecolor" => ColorDepth::TrueColor,
+            _ => ColorDepth::detect(),
+        }
+    }
+
+    fn detect() -> ColorDepth {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            return ColorDepth::TrueColor;
+        }
+        if env::var("TERM").unwrap_or_default().contains("256color") {
+            ColorDepth::Palette
+        } else {
+            ColorDepth::Basic
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Color {
+    /// An index into the 256-colour palette, the first 16 being the basic
+    /// colours.
+    Palette(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// A colour name or palette number, as for `color_index`, or `#rrggbb`.
+    pub fn parse(name: &str) -> Option<Color> {
+        if name.starts_with('#') && name.len() == 7 {
+            let channel = |i: usize| u8::from_str_radix(&name[i..i + 2], 16).ok();
+            return match (channel(1), channel(3), channel(5)) {
+                (Some(r), Some(g), Some(b)) => Some(Color::Rgb(r, g, b)),
+                _ => None,
+            };
+        }
+        color_index(name).map(Color::Palette)
+    }
+
+    pub fn rgb(&self) -> (u8, u8, u8) {
+        match *self {
+            Color::Palette(index) => palette_rgb(index),
+            Color::Rgb(r, g, b) => (r, g, b),
+        }
+    }
+
+    /// The nearest colour the terminal can show.
+    pub fn degrade(&self, depth: ColorDepth) -> Color {
+        match (*self, depth) {
+            (Color::Rgb(r, g, b), ColorDepth::Palette) => Color::Palette(nearest_palette((r, g, b))),
+            (Color::Rgb(..), ColorDepth::Basic) => Color::Palette(nearest_basic(self.rgb())),
+            (Color::Palette(index), ColorDepth::Basic) if index >= 16 => Color::Palette(nearest_basic(self.rgb())),
+            (color, _) => color,
+        }
+    }
+
+    fn escape(&self, background: bool, depth: ColorDepth) -> String {
+        match self.degrade(depth) {
+            Color::Rgb(r, g, b) => {
+                if background {
+                    color::Bg(color::Rgb(r, g, b)).to_string()
+                } else {
+                    color::Fg(color::Rgb(r, g, b)).to_string()
+                }
+            }
+            // The basic colours get the codes every terminal knows.
+            Color::Palette(index) if depth == ColorDepth::Basic => {
+                let base = if background { 40 } else { 30 };
+                let code = if index < 8 { base + index as u16 } else { base + 60 + (index - 8) as u16 };
+                format!("\x1b[{}m", code)
+            }
+            Color::Palette(index) => {
+                if background {
+                    color::Bg(color::AnsiValue(index)).to_string()
+                } else {
+                    color::Fg(color::AnsiValue(index)).to_string()
+                }
+            }
+        }
+    }
+}
+
+/// The RGB values xterm uses for the palette.
+pub fn palette_rgb(index: u8) -> (u8, u8, u8) {
+    const BASIC: [(u8, u8, u8); 16] = [
+        (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0),
+        (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+        (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0),
+        (92, 92, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255),
+    ];
+    match index {
+        0...15 => BASIC[index as usize],
+        16...231 => {
+            let cube = index - 16;
+            let level = |x: u8| if x == 0 { 0 } else { 55 + x * 40 };
+            (level(cube / 36), level(cube / 6 % 6), level(cube % 6))
+        }
+        _ => {
+            let gray = 8 + (index - 232) * 10;
+            (gray, gray, gray)
+        }
+    }
+}
+
+/// How bright a colour looks, from 0 to 255000.
+pub fn luma(rgb: (u8, u8, u8)) -> u32 {
+    299 * rgb.0 as u32 + 587 * rgb.1 as u32 + 114 * rgb.2 as u32
+}
+
+/// How far apart two colours look, roughly.
+pub fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let channel = |x: u8, y: u8| {
+        let d = x as i32 - y as i32;
+        (d * d) as u32
+    };
+    channel(a.0, b.0) + channel(a.1, b.1) + channel(a.2, b.2)
+}
+
+fn nearest_basic(rgb: (u8, u8, u8)) -> u8 {
+    (0..16).min_by_key(|&index| distance(palette_rgb(index), rgb)).unwrap_or(0)
+}
+
+/// The nearer of the closest colour in the 6×6×6 cube and the closest grey.
+fn nearest_palette(rgb: (u8, u8, u8)) -> u8 {
+    let level = |x: u8| if x < 48 { 0 } else if x < 115 { 1 } else { (x - 35) / 40 };
+    let cube = 16 + 36 * level(rgb.0) + 6 * level(rgb.1) + level(rgb.2);
+    let average = (rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3;
+    let gray = 232 + ((average.saturating_sub(8) + 5) / 10).min(23) as u8;
+    if distance(palette_rgb(gray), rgb) < distance(palette_rgb(cube), rgb) { gray } else { cube }
+}
+
+/// How an element is drawn.
+#[derive(Copy, Clone, Default, PartialEq)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+impl Style {
+    /// Parses a style such as `bold yellow on blue`. Colours are names,
+    /// palette numbers or `#rrggbb`, and `default` leaves one unset.
+    pub fn parse(spec: &str) -> Result<Style, String> {
+        let mut style = Style::default();
+        let mut background = false;
+        for word in spec.split_whitespace() {
+            match word {
+                "bold" => style.bold = true,
+                "italic" => style.italic = true,
+                "underline" => style.underline = true,
+                "reverse" => style.reverse = true,
+                "on" => background = true,
+                "default" => {}
+                _ => {
+                    let color = match Color::parse(word) {
+                        Some(color) => color,
+                        None => return Err(format!("unknown colour or attribute `{}`", word)),
+                    };
+                    if background {
+                        style.bg = Some(color);
+                    } else {
+                        style.fg = Some(color);
+                    }
+                }
+            }
+        }
+        Ok(style)
+    }
+
+    pub fn is_plain(&self) -> bool {
+        *self == Style::default()
+    }
+
+    /// This style drawn on top of another: attributes add up, and colours
+    /// that aren't set are taken from below.
+    pub fn over(&self, base: &Style) -> Style {
+        Style {
+            fg: self.fg.or(base.fg),
+            bg: self.bg.or(base.bg),
+            bold: self.bold || base.bold,
+            italic: self.italic || base.italic,
+            underline: self.underline || base.underline,
+            reverse: self.reverse || base.reverse,
+        }
+    }
+
+    /// The escape sequence that switches the terminal to this style from any
+    /// other.
+    pub fn ansi(&self, depth: ColorDepth) -> String {
+        let mut ansi = style::Reset.to_string();
+        if self.bold {
+            ansi.push_str(&style::Bold.to_string());
+        }
+        if self.italic {
+            ansi.push_str(&style::Italic.to_string());
+        }
+        if self.underline {
+            ansi.push_str(&style::Underline.to_string());
+        }
+        if self.reverse {
+            ansi.push_str(&style::Invert.to_string());
+        }
+        if let Some(fg) = self.fg {
+            ansi.push_str(&fg.escape(false, depth));
+        }
+        if let Some(bg) = self.bg {
+            ansi.push_str(&bg.escape(true, depth));
+        }
+        ansi
+    }
+}
+
+/// The styles of the theme picked with `theme` under `[ui]`, with those
+/// under `[ui.styles]` on top.
+pub struct Theme {
+    depth: ColorDepth,
+    styles: HashMap<String, Style>,
+    /// The palette colours nicks can get, and those pinned to nicks, by
+    /// their lowercased form. Both empty if nick colours are off.
+    nick_colors: Vec<Color>,
+    pinned: HashMap<String, Color>,
+}
+
+impl Theme {
+    /// Styles that don't parse are left out; the config check reports them.
+    pub fn new(ui: &Ui) -> Theme {
+        let theme = THEMES.iter().find(|&&(name, _, _)| name == ui.theme);
+        let background = theme.map(|&(_, background, _)| background).unwrap_or("black");
+        let builtin = theme.map(|&(_, _, styles)| styles).unwrap_or(&[]);
+        let mut styles = HashMap::new();
+        for &(element, spec) in builtin {
+            if let Ok(style) = Style::parse(spec) {
+                styles.insert(String::from(element), style);
+            }
+        }
+        for (element, spec) in &ui.styles {
+            if let Ok(style) = Style::parse(spec) {
+                styles.insert(element.clone(), style);
+            }
+        }
+
+        let nicks = &ui.nick_colors;
+        let mut nick_colors = Vec::new();
+        let mut pinned = HashMap::new();
+        if nicks.enabled {
+            let background = Color::parse(&nicks.background).or_else(|| Color::parse(background));
+            let background = background.map(|color| color.rgb()).unwrap_or((0, 0, 0));
+            let palette: Vec<Color> = nicks.palette.iter().filter_map(|name| Color::parse(name)).collect();
+            nick_colors = palette.iter().cloned().filter(|color| stands_out(color, background)).collect();
+            // Better colours close to the background than none at all.
+            if nick_colors.is_empty() {
+                nick_colors = palette;
+            }
+            for (nick, name) in &nicks.pinned {
+                if let Some(color) = Color::parse(name) {
+                    pinned.insert(irc_lower(nick), color);
+                }
+            }
+        }
+        Theme {
+            depth: ColorDepth::from_setting(&ui.color_depth),
+            styles: styles,
+            nick_colors: nick_colors,
+            pinned: pinned,
+        }
+    }
+
+    pub fn depth(&self) -> ColorDepth {
+        self.depth
+    }
+
+    pub fn style(&self, element: &str) -> Style {
+        self.styles.get(element).cloned().unwrap_or_default()
+    }
+
+    /// The escape sequence for an element's style.
+    pub fn ansi(&self, element: &str) -> String {
+        self.style(element).ansi(self.depth)
+    }
+
+    /// The style for someone else's nick: the `nick` style, in the colour
+    /// pinned to the nick or else one picked by hashing it. Nicks that only
+    /// differ in case get the same colour.
+    pub fn nick_style(&self, nick: &str) -> Style {
+        let style = self.style("nick");
+        let lower = irc_lower(nick);
+        let color = match self.pinned.get(&lower) {
+            Some(&color) => color,
+            None if self.nick_colors.is_empty() => return style,
+            None => self.nick_colors[hash(&lower) as usize % self.nick_colors.len()],
+        };
+        Style { fg: Some(color), ..style }
+    }
+
+    /// Text in an element's style, followed by a reset.
+    pub fn paint(&self, element: &str, text: &str) -> String {
+        let style = self.style(element);
+        if style.is_plain() {
+            return String::from(text);
+        }
+        format!("{}{}{}", style.ansi(self.depth), text, style::Reset)
+    }
+}
+
+/// Whether a colour is far enough in brightness from the background to be
+/// easy to read on it.
+fn stands_out(color: &Color, background: (u8, u8, u8)) -> bool {
+    // 15% of the range.
+    const MIN_CONTRAST: u32 = 38250;
+    let (a, b) = (luma(color.rgb()), luma(background));
+    (if a > b { a - b } else { b - a }) >= MIN_CONTRAST
+}
+
+/// 32-bit FNV-1a, which unlike the standard library's hasher is the same on
+/// every run, so nicks keep their colours.
+fn hash(text: &str) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in text.bytes() {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Color, ColorDepth, Style, palette_rgb};
+
+    #[test]
+    fn knows_the_palette() {
+        assert_eq!(palette_rgb(9), (255, 0, 0));
+        assert_eq!(palette_rgb(16), (0, 0, 0));
+        assert_eq!(palette_rgb(196), (255, 0, 0));
+        assert_eq!(palette_rgb(231), (255, 255, 255));
+        assert_eq!(palette_rgb(232), (8, 8, 8));
+        assert_eq!(palette_rgb(255), (238, 238, 238));
+    }
+
+    #[test]
+    fn keeps_colours_the_terminal_can_show() {
+        assert!(Color::Rgb(1, 2, 3).degrade(ColorDepth::TrueColor) == Color::Rgb(1, 2, 3));
+        assert!(Color::Palette(200).degrade(ColorDepth::Palette) == Color::Palette(200));
+        assert!(Color::Palette(5).degrade(ColorDepth::Basic) == Color::Palette(5));
+    }
+
+    #[test]
+    fn degrades_rgb_to_the_palette() {
+        assert!(Color::Rgb(255, 0, 0).degrade(ColorDepth::Palette) == Color::Palette(196));
+        // Greys are nearer on the grey ramp than in the colour cube.
+        assert!(Color::Rgb(128, 128, 128).degrade(ColorDepth::Palette) == Color::Palette(244));
+    }
+
+    #[test]
+    fn degrades_to_the_basic_colours() {
+        assert!(Color::Rgb(250, 10, 10).degrade(ColorDepth::Basic) == Color::Palette(9));
+        assert!(Color::Palette(196).degrade(ColorDepth::Basic) == Color::Palette(9));
+        assert!(Color::Palette(232).degrade(ColorDepth::Basic) == Color::Palette(0));
+    }
+
+    #[test]
+    fn basic_colours_get_the_old_codes() {
+        assert_eq!(Color::Palette(1).escape(false, ColorDepth::Basic), "\x1b[31m");
+        assert_eq!(Color::Palette(9).escape(false, ColorDepth::Basic), "\x1b[91m");
+        assert_eq!(Color::Palette(1).escape(true, ColorDepth::Basic), "\x1b[41m");
+        assert_eq!(Color::Rgb(255, 0, 0).escape(true, ColorDepth::Basic), "\x1b[101m");
+        assert_eq!(Color::Palette(1).escape(false, ColorDepth::Palette), "\x1b[38;5;1m");
+    }
+
+    #[test]
+    fn parses_styles() {
+        let style = Style::parse("bold yellow on #102030").unwrap();
+        assert!(style.bold && !style.underline);
+        assert!(style.fg == Color::parse("yellow"));
+        assert!(style.bg == Some(Color::Rgb(0x10, 0x20, 0x30)));
+        assert!(Style::parse("bold purplish").is_err());
+    }
+}
@@ -0,0 +1,70 @@
+use termion::{clear, cursor, style};
+
+use super::Settings;
+use super::format;
+use super::layout::SharedLayout;
+use super::theme::{Style, Theme};
+use super::window::{WindowId, Windows};
+
+/// The top row: the current channel's name, modes, user count and topic, or
+/// for a query, who it's with and whether they're away.
+pub struct TopicBar {
+    settings: Settings,
+    layout: SharedLayout,
+}
+
+impl TopicBar {
+    pub fn new(settings: Settings, layout: SharedLayout) -> TopicBar {
+        TopicBar {
+            settings: settings,
+            layout: layout,
+        }
+    }
+
+    /// How many rows it takes, 0 if it's turned off.
+    pub fn rows(&self) -> u16 {
+        if self.settings.borrow().ui.topic_bar { 1 } else { 0 }
+    }
+
+    pub fn draw(&self, windows: &Windows, network: &str) {
+        if self.layout.topic_rows() == 0 {
+            return;
+        }
+        let window = windows.current_window();
+        let (title, text) = match *window.id() {
+            WindowId::Channel { ref name } => {
+                let mut title = name.clone();
+                if !window.modes().is_empty() {
+                    title.push_str(&format!(" [{}]", window.modes()));
+                }
+                title.push_str(&format!(" {} users", window.user_count()));
+                (title, window.topic().map(String::from).unwrap_or_default())
+            }
+            WindowId::Query { ref name } => {
+                let (host, away) = windows.user_info(name);
+                let mut title = name.clone();
+                if let Some(host) = host {
+                    title.push_str(&format!(" ({})", host));
+                }
+                let text = away.map(|away| format!("Away: {}", away)).unwrap_or_default();
+                (title, text)
+            }
+            WindowId::Status => (String::from(network), String::new()),
+        };
+
+        let (cols, _) = self.layout.size();
+        let width = cols as usize;
+        let theme = Theme::new(&self.settings.borrow().ui);
+        let base = theme.style("topicbar");
+        let base_ansi = base.ansi(theme.depth());
+        // Formatting codes in the title or topic reset the style, so it's set
+        // again after each.
+        let (title, used) = format::render(&title, width);
+        print!("{}{}{}{}", cursor::Goto(1, 1), Style { bold: true, ..base }.ansi(theme.depth()), title, base_ansi);
+        if !text.is_empty() && used + 3 < width {
+            let (text, _) = format::render(&text, width - used - 3);
+            print!(" │ {}{}", text, base_ansi);
+        }
+        print!("{}{}", clear::UntilNewline, style::Reset);
+    }
+}
@@ -0,0 +1,134 @@
+use std::time::{Duration, Instant};
+
+use irc::misc::irc_equal;
+
+/// Minimum time between two `+typing=active` notifications.
+const ACTIVE_INTERVAL: u64 = 3;
+/// Time without input after which we tell others we've paused.
+const PAUSE_AFTER: u64 = 6;
+/// Time after which a received notification is considered stale.
+const EXPIRE_AFTER: u64 = 30;
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Active,
+    Paused,
+    Done,
+}
+
+/// Decides which `+typing` notifications to send as the entry line changes.
+pub struct TypingNotifier {
+    enabled: bool,
+    target: Option<String>,
+    state: State,
+    last_active: Option<Instant>,
+    last_input: Instant,
+}
+
+impl TypingNotifier {
+    pub fn new(enabled: bool) -> TypingNotifier {
+        TypingNotifier {
+            enabled: enabled,
+            target: None,
+            state: State::Done,
+            last_active: None,
+            last_input: Instant::now(),
+        }
+    }
+
+    /// Returns a `done` notification if we were typing when disabled.
+    pub fn set_enabled(&mut self, enabled: bool) -> Option<(String, &'static str)> {
+        self.enabled = enabled;
+        if enabled || self.state == State::Done {
+            return None;
+        }
+        self.state = State::Done;
+        self.last_active = None;
+        self.target.clone().map(|target| (target, "done"))
+    }
+
+    /// Called after the entry line was edited. Returns the notifications to
+    /// send as `(target, state)`.
+    pub fn input(&mut self, target: Option<&str>, empty: bool) -> Vec<(String, &'static str)> {
+        let mut notifications = Vec::new();
+        if !self.enabled {
+            return notifications;
+        }
+        let now = Instant::now();
+        if self.target.as_ref().map(|x| &x[..]) != target {
+            if let Some(old_target) = self.target.take() {
+                if self.state != State::Done {
+                    notifications.push((old_target, "done"));
+                }
+            }
+            self.target = target.map(String::from);
+            self.state = State::Done;
+            self.last_active = None;
+        }
+        let target = match self.target {
+            Some(ref target) => target.clone(),
+            None => return notifications,
+        };
+        if empty {
+            if self.state != State::Done {
+                notifications.push((target, "done"));
+                self.state = State::Done;
+                self.last_active = None;
+            }
+            return notifications;
+        }
+        let interval = Duration::from_secs(ACTIVE_INTERVAL);
+        let resend = self.last_active.map(|x| now.duration_since(x) >= interval).unwrap_or(true);
+        if self.state != State::Active || resend {
+            notifications.push((target, "active"));
+            self.last_active = Some(now);
+        }
+        self.state = State::Active;
+        self.last_input = now;
+        notifications
+    }
+
+    pub fn tick(&mut self) -> Option<(String, &'static str)> {
+        if self.state != State::Active
+            || self.last_input.elapsed() < Duration::from_secs(PAUSE_AFTER) {
+            return None;
+        }
+        self.state = State::Paused;
+        self.target.clone().map(|target| (target, "paused"))
+    }
+
+    /// Sending a message implies we're done typing, so there's no need to
+    /// say so.
+    pub fn sent(&mut self) {
+        self.state = State::Done;
+        self.last_active = None;
+    }
+}
+
+/// Nicks that have told us they're typing in a window.
+pub struct TypingNicks {
+    nicks: Vec<(String, Instant)>,
+}
+
+impl TypingNicks {
+    pub fn new() -> TypingNicks {
+        TypingNicks {
+            nicks: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, nick: &str, state: &str) {
+        self.nicks.retain(|&(ref x, _)| !irc_equal(x, nick));
+        if state == "active" {
+            self.nicks.push((String::from(nick), Instant::now()));
+        }
+    }
+
+    pub fn active(&self) -> Vec<&str> {
+        let expire = Duration::from_secs(EXPIRE_AFTER);
+        self.nicks.iter()
+            .filter(|&&(_, time)| time.elapsed() < expire)
+            .map(|&(ref nick, _)| &nick[..])
+            .collect()
+    }
+}
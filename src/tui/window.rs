@@ -1,9 +1,28 @@
 use std::cell::Cell;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
 use super::displayarea::DisplayArea;
+use super::nicklist::{NickList, Prefixes, Users};
+use super::modes::Modes;
+use super::typing::TypingNicks;
+use super::Settings;
+use super::layout::{Rect, SharedLayout};
+use super::pane::{Direction, PaneId, Panes};
+use super::session::{Kind, SavedWindow};
+
+use termion::{clear, cursor, style};
+use unicode_width::UnicodeWidthStr;
 
 use event;
-use irc::misc::irc_equal;
+use irc::misc::{irc_equal, irc_lower, is_channel};
+
+/// How many visited windows are remembered.
+const HISTORY_LEN: usize = 20;
+/// Seconds to wait for CHATHISTORY before giving up on it.
+const CHATHISTORY_TIMEOUT: u64 = 30;
+/// Seconds to wait for a labeled reply before forgetting its label.
+const LABEL_TIMEOUT: u64 = 60;
 
 #[derive(Clone)]
 pub enum WindowId {
@@ -50,22 +69,56 @@ pub struct Window {
     display: DisplayArea,
     id: WindowId,
     active: Cell<ActivityLevel>,
+    /// Messages and highlights since the window was last looked at.
+    unread: Cell<usize>,
+    highlights: Cell<usize>,
+    nicks: NickList,
+    topic: Option<String>,
+    modes: Modes,
+    typing: TypingNicks,
+    settings: Settings,
 }
 
 impl Window {
-    fn new(id: WindowId) -> Window {
+    fn new(id: WindowId, settings: Settings, layout: SharedLayout) -> Window {
+        let display = DisplayArea::new(id.name().unwrap_or("Status"), settings.clone(), layout);
         Window {
-            display: DisplayArea::new(),
+            display: display,
             id: id,
             active: Cell::new(ActivityLevel::Inactive),
+            unread: Cell::new(0),
+            highlights: Cell::new(0),
+            nicks: NickList::new(),
+            topic: None,
+            modes: Modes::new(),
+            typing: TypingNicks::new(),
+            settings: settings,
         }
     }
 
     fn redraw(&self) {
-        self.active.set(ActivityLevel::Inactive);
+        self.mark_seen();
         self.display.redraw();
     }
 
+    fn mark_seen(&self) {
+        self.active.set(ActivityLevel::Inactive);
+        self.unread.set(0);
+        self.highlights.set(0);
+    }
+
+    pub fn activity(&self) -> ActivityLevel {
+        self.active.get()
+    }
+
+    pub fn unread(&self) -> usize {
+        self.unread.get()
+    }
+
+    pub fn highlights(&self) -> usize {
+        self.highlights.get()
+    }
+
     pub fn name(&self) -> &str {
         self.id.name().unwrap_or("Status")
     }
@@ -74,25 +127,89 @@ impl Window {
         &self.id
     }
 
-    pub fn self_message(&self, message: &str) {
-        self.display.self_message(message);
+    /// Returns the msgid to request older history before, if the top of the
+    /// scrollback was reached and no request is in flight.
+    pub fn scroll_up(&self) -> Option<String> {
+        let at_top = self.display.scroll_up();
+        self.display.redraw();
+        if at_top && !self.display.is_loading() {
+            self.display.oldest_msgid()
+        } else {
+            None
+        }
+    }
+
+    pub fn scroll_down(&self) {
+        self.display.scroll_down();
+        self.display.redraw();
+    }
+
+    /// How many messages are scrolled out of view at the bottom.
+    pub fn scrolled(&self) -> usize {
+        self.display.scrolled()
+    }
+
+    /// Scrolls to the oldest unread message. Returns false if there's none.
+    pub fn scroll_to_unread(&self) -> bool {
+        self.display.scroll_to_unread()
+    }
+
+    pub fn topic(&self) -> Option<&str> {
+        self.topic.as_ref().map(|x| &x[..])
+    }
+
+    pub fn modes(&self) -> &Modes {
+        &self.modes
+    }
+
+    pub fn user_count(&self) -> usize {
+        self.nicks.len()
+    }
+
+    pub fn typing_nicks(&self) -> Vec<&str> {
+        self.typing.active()
     }
 
     pub fn show_event(&self, event: &event::ChatEvent) {
-        let active = self.active.get();
-        if active == ActivityLevel::Inactive {
-            if event.is_query {
-                self.active.set(ActivityLevel::Hilight);
+        use irc_lib::client::data::Command::{PRIVMSG, NOTICE};
+        let highlight = !event.about_self && self.is_highlight(event);
+        if !event.about_self {
+            let is_message = match event.message.command {
+                PRIVMSG(..) | NOTICE(..) => true,
+                _ => false,
+            };
+            if is_message {
+                self.unread.set(self.unread.get() + 1);
+            }
+            if event.is_query || highlight {
+                if self.settings.borrow().notifications.bell {
+                    print!("\x07");
+                }
+                self.highlights.set(self.highlights.get() + 1);
+                self.set_active(ActivityLevel::Hilight);
             } else {
-                self.active.set(ActivityLevel::Active);
+                self.set_active(ActivityLevel::Active);
             }
         }
-        self.display.show_event(event);
+        let prefix = event.source_nickname().and_then(|nick| self.nicks.prefix(nick)).unwrap_or("");
+        self.display.show_event(event, highlight, prefix);
     }
 
-    pub fn update_display(&self) {
-        self.active.set(ActivityLevel::Inactive);
-        self.display.update_display();
+    fn is_highlight(&self, event: &event::ChatEvent) -> bool {
+        use irc_lib::client::data::Command::{PRIVMSG, NOTICE};
+        let text = match event.message.command {
+            PRIVMSG(_, ref text) | NOTICE(_, ref text) => text.to_lowercase(),
+            _ => return false,
+        };
+        let settings = self.settings.borrow();
+        settings.notifications.highlight_words.iter()
+            .any(|word| text.contains(&word.to_lowercase()))
+    }
+
+    fn set_active(&self, level: ActivityLevel) {
+        if self.active.get() < level {
+            self.active.set(level);
+        }
     }
 }
 
@@ -104,16 +221,44 @@ enum WindowPosition {
 
 pub struct Windows {
     status: Window,
+    settings: Settings,
+    layout: SharedLayout,
     windows: Vec<Window>,
     current_window: WindowPosition,
+    users: Users,
+    /// Our nick, once the server has told us.
+    nick: String,
+    away: bool,
+    user_modes: Modes,
+    prefixes: Prefixes,
+    /// The window each label was issued from, and when.
+    labels: HashMap<String, (WindowId, Instant)>,
+    next_label: usize,
+    /// Windows visited before the current one, the latest last.
+    history: Vec<WindowId>,
+    panes: Panes,
+    /// Set when the panes have to be drawn from scratch.
+    layout_changed: Cell<bool>,
 }
 
 impl Windows {
-    pub fn new() -> Windows {
+    pub fn new(settings: Settings, layout: SharedLayout) -> Windows {
         Windows {
-            status: Window::new(WindowId::Status),
+            status: Window::new(WindowId::Status, settings.clone(), layout.clone()),
+            settings: settings,
+            layout: layout,
             windows: Vec::new(),
             current_window: WindowPosition::Status,
+            users: Users::new(),
+            nick: String::new(),
+            away: false,
+            user_modes: Modes::new(),
+            prefixes: Prefixes::new(),
+            labels: HashMap::new(),
+            next_label: 0,
+            history: Vec::new(),
+            panes: Panes::new(),
+            layout_changed: Cell::new(true),
         }
     }
 
@@ -128,13 +273,165 @@ impl Windows {
         }
     }
 
-    pub fn current_window_number(&self) -> usize {
-        match self.current_window {
-            WindowPosition::Status => 0,
-            WindowPosition::Other(i) => i + 1,
+    /// Shows a message from the client itself, such as an error, in the
+    /// current window.
+    pub fn show_info(&self, message: &str) {
+        let window = self.current_window();
+        window.display.add_message(message);
+        window.redraw();
+    }
+
+    /// Shows a message in the Status window, such as the result of a command
+    /// that isn't about any other window.
+    pub fn show_status(&self, message: &str) {
+        self.status.display.add_message(message);
+        if self.current_window == WindowPosition::Status {
+            self.status.redraw();
+        } else {
+            self.status.set_active(ActivityLevel::Active);
         }
     }
 
+    /// Draws everything again, such as after the layout changed.
+    pub fn redraw(&self) {
+        self.layout_changed.set(true);
+    }
+
+    /// Draws the panes whose windows changed since they were last drawn.
+    /// With more than one pane, each gets a title row at the bottom.
+    pub fn draw(&self) {
+        let everything = self.layout_changed.get();
+        self.layout_changed.set(false);
+        if everything {
+            print!("{}", clear::All);
+        }
+        let area = self.layout.display_rect();
+        let rects = self.panes.rects(area);
+        let titled = rects.len() > 1;
+        let windows: Vec<&Window> = rects.iter().map(|&(pane, _)| self.pane_window(pane)).collect();
+        let dirty: Vec<bool> = windows.iter().map(|window| window.display.is_dirty()).collect();
+        for (i, &(pane, rect)) in rects.iter().enumerate() {
+            if !everything && !dirty[i] {
+                continue;
+            }
+            let window = windows[i];
+            window.mark_seen();
+            let mut content = rect;
+            if titled {
+                content.height = content.height.saturating_sub(1);
+                self.draw_title(pane, window, rect);
+            }
+            window.display.draw(content);
+            if everything && rect.x + rect.width < area.x + area.width {
+                for y in rect.y..rect.y + rect.height {
+                    print!("{}│", cursor::Goto(rect.x + rect.width, y));
+                }
+            }
+        }
+    }
+
+    fn draw_title(&self, pane: PaneId, window: &Window, rect: Rect) {
+        if rect.height == 0 || rect.width == 0 {
+            return;
+        }
+        let number = self.position_of(window.id()).map(|position| self.number_of(position)).unwrap_or(0);
+        let mut title = format!(" {}: {} ", number, window.name());
+        let width = rect.width as usize;
+        while UnicodeWidthStr::width(&title[..]) > width {
+            title.pop();
+        }
+        let padding = width - UnicodeWidthStr::width(&title[..]);
+        let style = if pane == self.panes.focused() { style::Invert.to_string() } else { String::new() };
+        print!("{}{}{}{}{}", cursor::Goto(rect.x, rect.y + rect.height - 1),
+            style, title, "─".repeat(padding), style::Reset);
+    }
+
+    /// The window a pane shows. The focused pane shows the current window.
+    fn pane_window(&self, pane: PaneId) -> &Window {
+        if pane == self.panes.focused() {
+            return self.current_window();
+        }
+        let position = self.position_of(&self.panes.window(pane)).unwrap_or(WindowPosition::Status);
+        self.window_by_position(position)
+    }
+
+    /// Splits the focused pane in two. The new pane gets the focus, and
+    /// starts out showing the same window.
+    pub fn split(&mut self, direction: Direction) {
+        let current = self.current_window().id().clone();
+        self.panes.split(direction, current);
+        self.redraw();
+    }
+
+    /// Closes a pane by number, or the focused one.
+    pub fn close_pane(&mut self, number: Option<usize>) -> Result<(), String> {
+        let pane = match number {
+            Some(number) => match self.panes.by_number(number) {
+                Some(pane) => pane,
+                None => return Err(format!("There's no pane {}.", number)),
+            },
+            None => self.panes.focused(),
+        };
+        if let Some(id) = try!(self.panes.close(pane)) {
+            self.current_window().display.set_marker();
+            self.current_window = self.position_of(&id).unwrap_or(WindowPosition::Status);
+        }
+        self.redraw();
+        Ok(())
+    }
+
+    /// Closes every pane but the focused one.
+    pub fn only_pane(&mut self) {
+        let focused = self.panes.focused();
+        for pane in self.panes.ids() {
+            if pane != focused {
+                self.panes.close(pane).ok();
+            }
+        }
+        self.redraw();
+    }
+
+    pub fn focus_pane(&mut self, number: usize) -> Result<(), String> {
+        let pane = match self.panes.by_number(number) {
+            Some(pane) => pane,
+            None => return Err(format!("There's no pane {}.", number)),
+        };
+        let current = self.current_window().id().clone();
+        self.current_window().display.set_marker();
+        let id = self.panes.focus(pane, current);
+        self.current_window = self.position_of(&id).unwrap_or(WindowPosition::Status);
+        self.redraw();
+        Ok(())
+    }
+
+    pub fn next_pane(&mut self) {
+        let number = self.panes.number_of(self.panes.focused());
+        let next = number % self.panes.count() + 1;
+        self.focus_pane(next).ok();
+    }
+
+    pub fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    /// What's known about a user, for the topic bar of a query: their
+    /// user@host and away message.
+    pub fn user_info(&self, nick: &str) -> (Option<&str>, Option<&str>) {
+        (self.users.host(nick), self.users.away(nick))
+    }
+
+    pub fn is_away(&self) -> bool {
+        self.away
+    }
+
+    pub fn user_modes(&self) -> &Modes {
+        &self.user_modes
+    }
+
+    pub fn current_window_number(&self) -> usize {
+        self.number_of(self.current_window)
+    }
+
     pub fn highest_window_index(&self) -> usize {
         self.windows.len()
     }
@@ -147,44 +444,348 @@ impl Windows {
         }
     }
 
-    pub fn close_current(&mut self) {
-        use self::WindowPosition as C;
+    /// Closes a window by number, and goes back to the window visited before
+    /// it if it was the current one. Returns the closed window's id.
+    pub fn close(&mut self, number: usize) -> Option<WindowId> {
+        if number == 0 || number > self.windows.len() {
+            return None;
+        }
+        let index = number - 1;
+        let id = self.windows.remove(index).id;
+        self.history.retain(|x| *x != id);
+        self.panes.window_closed(&id);
         match self.current_window {
-            C::Status => {}
-            C::Other(i) => {
-                if i < self.windows.len() {
-                    self.windows.remove(i);
-                }
+            WindowPosition::Other(i) if i == index => {
+                self.current_window = self.history.pop()
+                    .and_then(|id| self.position_of(&id))
+                    .unwrap_or(WindowPosition::Status);
             }
+            WindowPosition::Other(i) if i > index => {
+                self.current_window = WindowPosition::Other(i - 1);
+            }
+            _ => {}
         }
-        self.current_window = C::Status;
+        self.current_window().redraw();
+        Some(id)
     }
 
     pub fn change_to(&mut self, i: usize) {
-        if i == 0 {
-            self.current_window = WindowPosition::Status;
+        let position = if i == 0 {
+            WindowPosition::Status
         } else if i <= self.windows.len() {
-            self.current_window = WindowPosition::Other(i-1);
+            WindowPosition::Other(i-1)
+        } else {
+            self.current_window
+        };
+        if position != self.current_window {
+            self.current_window().display.set_marker();
+            let id = self.current_window().id().clone();
+            self.history.retain(|x| *x != id);
+            self.history.push(id);
+            if self.history.len() > HISTORY_LEN {
+                self.history.remove(0);
+            }
         }
+        self.current_window = position;
         self.current_window().redraw();
     }
 
+    /// Goes back to the window visited last. Doing it again comes back.
+    pub fn last_window(&mut self) {
+        let previous = self.history.last().and_then(|id| self.position_of(id));
+        if let Some(position) = previous {
+            let number = self.number_of(position);
+            self.change_to(number);
+        }
+    }
+
+    /// Moves the current window to another number, shifting the ones in
+    /// between.
+    pub fn move_current(&mut self, number: usize) -> Result<(), String> {
+        let index = match self.current_window {
+            WindowPosition::Other(i) => i,
+            WindowPosition::Status => return Err(String::from("The Status window can't be moved.")),
+        };
+        if number == 0 || number > self.windows.len() {
+            return Err(format!("There's no window {}.", number));
+        }
+        let window = self.windows.remove(index);
+        self.windows.insert(number - 1, window);
+        self.current_window = WindowPosition::Other(number - 1);
+        // Pane titles show window numbers.
+        self.redraw();
+        Ok(())
+    }
+
+    pub fn swap(&mut self, a: usize, b: usize) -> Result<(), String> {
+        for &number in &[a, b] {
+            if number == 0 || number > self.windows.len() {
+                return Err(format!("There's no window {}, and the Status window can't be moved.", number));
+            }
+        }
+        self.windows.swap(a - 1, b - 1);
+        self.current_window = match self.current_window {
+            WindowPosition::Other(i) if i == a - 1 => WindowPosition::Other(b - 1),
+            WindowPosition::Other(i) if i == b - 1 => WindowPosition::Other(a - 1),
+            position => position,
+        };
+        self.redraw();
+        Ok(())
+    }
+
+    /// Finds a window by number or name. Names match exactly, then by
+    /// prefix, then as a substring, and then fuzzily, with the letters in
+    /// order but not necessarily together. The `#` of channels is optional.
+    pub fn find(&self, query: &str) -> Option<usize> {
+        if let Ok(number) = query.parse::<usize>() {
+            return if number <= self.windows.len() { Some(number) } else { None };
+        }
+        let query = irc_lower(query.trim_left_matches('#'));
+        if query.is_empty() {
+            return None;
+        }
+        let names: Vec<String> = self.windows.iter()
+            .map(|window| irc_lower(window.name().trim_left_matches('#')))
+            .collect();
+        let exact = names.iter().position(|name| *name == query);
+        exact
+            .or_else(|| names.iter().position(|name| name.starts_with(&query[..])))
+            .or_else(|| names.iter().position(|name| name.contains(&query[..])))
+            .or_else(|| names.iter().position(|name| is_subsequence(&query, name)))
+            .map(|index| index + 1)
+    }
+
+    /// One line per window, for `/window list`.
+    pub fn list(&self) -> Vec<String> {
+        let current = self.current_window_number();
+        let mut lines = Vec::with_capacity(self.windows.len() + 1);
+        for (number, window) in self.iter().enumerate() {
+            let activity = match window.active.get() {
+                ActivityLevel::Inactive => "",
+                ActivityLevel::Active => " (activity)",
+                ActivityLevel::Hilight => " (highlight)",
+            };
+            let marker = if number == current { "*" } else { " " };
+            lines.push(format!("{}{:>3}: {}{}", marker, number, window.name(), activity));
+        }
+        lines
+    }
+
+    fn position_of(&self, id: &WindowId) -> Option<WindowPosition> {
+        if *id == WindowId::Status {
+            return Some(WindowPosition::Status);
+        }
+        self.windows.iter().position(|window| window.id == *id).map(WindowPosition::Other)
+    }
+
+    fn number_of(&self, position: WindowPosition) -> usize {
+        match position {
+            WindowPosition::Status => 0,
+            WindowPosition::Other(i) => i + 1,
+        }
+    }
+
+    pub fn next_window(&mut self) {
+        let next = (self.current_window_number() + 1) % (self.windows.len() + 1);
+        self.change_to(next);
+    }
+
+    pub fn previous_window(&mut self) {
+        let count = self.windows.len() + 1;
+        let previous = (self.current_window_number() + count - 1) % count;
+        self.change_to(previous);
+    }
+
+    /// Goes to the next window with a highlight, or failing that, the next
+    /// one with any activity, or failing that, the last one visited.
+    pub fn next_active(&mut self) {
+        let current = self.current_window_number();
+        let count = self.windows.len() + 1;
+        let activity: Vec<_> = self.activity().collect();
+        let after_current = |level: ActivityLevel| {
+            (1..count)
+                .map(|offset| (current + offset) % count)
+                .find(|&i| activity[i].1 == level)
+        };
+        let next = after_current(ActivityLevel::Hilight).or_else(|| after_current(ActivityLevel::Active));
+        match next {
+            Some(next) => self.change_to(next),
+            // Once everything's been read, go back to where we started.
+            None => self.last_window(),
+        }
+    }
+
     pub fn handle_event(&mut self, event: event::ChatEvent) {
+        self.route_event(&event);
+        self.current_window().redraw();
+    }
+
+    pub fn handle_batch(&mut self, batch: event::BatchEvent) {
+        use irc_lib::client::data::Command::*;
+        match &batch.kind[..] {
+            "netsplit" => {
+                let nicks: Vec<_> = batch.events.iter().filter_map(|e| e.source_nickname()).collect();
+                self.status.display.add_message(format!("Netsplit {}: {}",
+                    batch.params.join(" <-> "),
+                    nicks.join(", ")));
+                self.status.set_active(ActivityLevel::Active);
+            }
+            "netjoin" => {
+                let mut channels: Vec<(String, Vec<&str>)> = Vec::new();
+                for event in &batch.events {
+                    if let JOIN(ref channel, _, _) = event.message.command {
+                        let nick = event.source_nickname().unwrap_or("");
+                        match channels.iter().position(|&(ref name, _)| irc_equal(name, channel)) {
+                            Some(i) => channels[i].1.push(nick),
+                            None => channels.push((channel.clone(), vec![nick])),
+                        }
+                    }
+                }
+                for (channel, nicks) in channels {
+                    let index = self.open(&channel, false);
+                    let window = &self.windows[index];
+                    window.display.add_message(format!("Netsplit over, joined {}: {}", channel, nicks.join(", ")));
+                    window.set_active(ActivityLevel::Active);
+                }
+            }
+            "chathistory" | "draft/chathistory" => {
+                // History only goes to windows that are still open.
+                let index = match batch.params.get(0).and_then(|target| self.get_index_by_name(target)) {
+                    Some(index) => index,
+                    None => return,
+                };
+                let window = &self.windows[index];
+                for event in batch.events.iter().filter(|event| !self.is_ignored(event)) {
+                    let highlight = !event.about_self && window.is_highlight(event);
+                    let prefix = event.source_nickname().and_then(|nick| window.nicks.prefix(nick)).unwrap_or("");
+                    window.display.insert_event(event, highlight, prefix);
+                }
+                window.display.set_loading(false);
+                if self.current_window == WindowPosition::Other(index) {
+                    window.redraw();
+                }
+            }
+            "labeled-response" => {
+                let position = batch.label.as_ref().and_then(|label| self.labeled_position(label));
+                for event in &batch.events {
+                    let default_position = self.event_position(event);
+                    self.show_event_at(position.unwrap_or(default_position), event);
+                }
+            }
+            _ => {
+                for event in &batch.events {
+                    self.route_event(event);
+                }
+            }
+        }
+        self.current_window().redraw();
+    }
+
+    fn route_event(&mut self, event: &event::ChatEvent) {
+        use irc_lib::client::data::Command::{Raw, AWAY, Response};
+        use irc_lib::client::data::Response::RPL_AWAY;
+        if self.is_ignored(event) {
+            return;
+        }
+        if is_history_error(event) {
+            self.stop_loading();
+        }
+        match event.message.command {
+            // A labeled command with nothing to reply gets a bare ACK.
+            Raw(ref command, _, _) if command == "ACK" => {
+                if let Some(label) = event.tags.get("label") {
+                    self.labels.remove(label);
+                }
+                return;
+            }
+            Raw(ref command, ref args, _) if command == "TAGMSG" => {
+                let target = args.get(0).map(|x| &x[..]).unwrap_or("");
+                self.handle_tagmsg(event, target);
+                return;
+            }
+            // away-notify changes only show up in the nick list.
+            AWAY(ref message) => {
+                let source = event.source_nickname().unwrap_or("");
+                self.users.set_away(source, message.as_ref().map(|x| &x[..]));
+                return;
+            }
+            // RPL_AWAY comes with every message sent to the user, so it's only
+            // shown when the away message changes.
+            Response(RPL_AWAY, ref args, ref message) => {
+                let nick = args.get(1).map(|x| &x[..]).unwrap_or("");
+                let message = message.as_ref().map(|x| &x[..]).unwrap_or("");
+                if self.users.away(nick) == Some(message) {
+                    return;
+                }
+                self.users.set_away(nick, Some(message));
+                let position = match self.get_index_by_name(nick) {
+                    Some(index) => WindowPosition::Other(index),
+                    None => WindowPosition::Status,
+                };
+                self.window_by_position(position).display.add_message(format!("{} is away: {}", nick, message));
+                return;
+            }
+            _ => {}
+        }
+        let mut window_position = self.event_position(event);
+        if let Some(label) = event.tags.get("label") {
+            if let Some(position) = self.labeled_position(label) {
+                window_position = position;
+            }
+        }
+        self.show_event_at(window_position, event);
+    }
+
+    /// Only messages are ignored, so that channel state stays correct.
+    fn is_ignored(&self, event: &event::ChatEvent) -> bool {
+        use irc_lib::client::data::Command::{PRIVMSG, NOTICE, Raw};
+        let is_message = match event.message.command {
+            PRIVMSG(..) | NOTICE(..) => true,
+            Raw(ref command, _, _) => command == "TAGMSG",
+            _ => false,
+        };
+        if !is_message || event.about_self {
+            return false;
+        }
+        match event.message.prefix {
+            Some(ref prefix) => self.settings.borrow().is_ignored(prefix),
+            None => false,
+        }
+    }
+
+    /// Updates channel state from the event, and returns the window the event
+    /// belongs in.
+    fn event_position(&mut self, event: &event::ChatEvent) -> WindowPosition {
         use irc_lib::client::data::Command::*;
+        use irc_lib::client::data::Response::{RPL_WELCOME, RPL_NAMREPLY, RPL_NOWAWAY, RPL_UNAWAY};
+        use irc_lib::client::data::Response::{RPL_TOPIC, RPL_NOTOPIC, RPL_CHANNELMODEIS, RPL_WHOISUSER, RPL_UMODEIS};
+        use irc_lib::client::data::Response::RPL_BOUNCE;
+        let source = event.source_nickname().unwrap_or("");
+        if let Some(account) = event.tags.get("account") {
+            self.users.set_account(source, account);
+        }
+        if let Some(ref prefix) = event.message.prefix {
+            if let Some(bang) = prefix.find('!') {
+                self.users.set_host(source, &prefix[bang + 1..]);
+            }
+        }
         let window_position;
         match event.message.command {
             PRIVMSG(ref target, _) => {
                 let window_index = if event.is_query {
-                    let source = event.message.source_nickname().unwrap_or("Unknown nick");
-                    self.open(source, true)
+                    // Our own messages to a query are echoed with the peer as
+                    // the target.
+                    let name = if event.about_self { &target[..] } else { source };
+                    self.open(name, true)
                 } else {
                     self.open(target, false)
                 };
+                self.windows[window_index].typing.update(source, "done");
                 window_position = WindowPosition::Other(window_index);
             }
             NOTICE(ref target, _) => {
-                let name = if event.is_query {
-                    event.message.source_nickname().unwrap_or("Unknown nick")
+                let name = if event.is_query && !event.about_self {
+                    source
                 } else {
                     target
                 };
@@ -196,15 +797,280 @@ impl Windows {
             }
             JOIN(ref channel, _, _) => {
                 let window_index = self.open(channel, false);
+                let nicks = &mut self.windows[window_index].nicks;
+                if event.about_self {
+                    nicks.clear();
+                }
+                nicks.add(source, &self.prefixes);
                 window_position = WindowPosition::Other(window_index);
             }
+            PART(ref channel, _) => {
+                if let Some(index) = self.get_index_by_name(channel) {
+                    self.windows[index].nicks.remove(source);
+                }
+                window_position = WindowPosition::Status;
+            }
+            KICK(ref channel, ref nick, _) => {
+                if let Some(index) = self.get_index_by_name(channel) {
+                    self.windows[index].nicks.remove(nick);
+                }
+                window_position = WindowPosition::Status;
+            }
+            QUIT(_) => {
+                for window in &mut self.windows {
+                    window.nicks.remove(source);
+                }
+                self.users.remove(source);
+                window_position = WindowPosition::Status;
+            }
+            NICK(ref new_nick) => {
+                if event.about_self {
+                    self.nick = new_nick.clone();
+                }
+                for window in &mut self.windows {
+                    window.nicks.rename(source, new_nick);
+                }
+                self.users.rename(source, new_nick);
+                window_position = WindowPosition::Status;
+            }
+            ACCOUNT(ref account) => {
+                self.users.set_account(source, account);
+                window_position = WindowPosition::Status;
+            }
+            Response(RPL_WELCOME, ref args, _) => {
+                if let Some(nick) = args.get(0) {
+                    self.nick = nick.clone();
+                }
+                window_position = WindowPosition::Status;
+            }
+            Response(RPL_NOWAWAY, _, _) => {
+                self.away = true;
+                window_position = WindowPosition::Status;
+            }
+            Response(RPL_UNAWAY, _, _) => {
+                self.away = false;
+                window_position = WindowPosition::Status;
+            }
+            TOPIC(ref channel, ref topic) => {
+                window_position = self.set_topic(channel, topic.clone());
+            }
+            Response(RPL_TOPIC, ref args, ref topic) => {
+                let channel = args.get(1).map(|x| &x[..]).unwrap_or("");
+                window_position = self.set_topic(channel, topic.clone());
+            }
+            Response(RPL_NOTOPIC, ref args, _) => {
+                let channel = args.get(1).map(|x| &x[..]).unwrap_or("");
+                window_position = self.set_topic(channel, None);
+            }
+            // MODE is only parsed with up to one parameter; with more, it's a
+            // Raw command.
+            MODE(ref target, ref modes, ref param) if is_channel(target) => {
+                let params: Vec<&str> = param.iter().map(|x| &x[..]).collect();
+                window_position = self.change_modes(target, modes, &params, false);
+            }
+            Raw(ref command, ref args, ref suffix) if command == "MODE" && args.len() >= 2 && is_channel(&args[0]) => {
+                let params: Vec<&str> = args[2..].iter().chain(suffix.iter()).map(|x| &x[..]).collect();
+                window_position = self.change_modes(&args[0], &args[1], &params, false);
+            }
+            MODE(ref target, ref modes, _) if irc_equal(target, &self.nick) => {
+                self.user_modes.apply(modes, &[], &Prefixes::none());
+                window_position = WindowPosition::Status;
+            }
+            Response(RPL_UMODEIS, ref args, ref suffix) => {
+                let modes = args.get(1).or(suffix.as_ref()).map(|x| &x[..]).unwrap_or("");
+                self.user_modes.set(modes, &[]);
+                window_position = WindowPosition::Status;
+            }
+            Response(RPL_CHANNELMODEIS, ref args, ref suffix) if args.len() >= 3 => {
+                let params: Vec<&str> = args[3..].iter().chain(suffix.iter()).map(|x| &x[..]).collect();
+                window_position = self.change_modes(&args[1], &args[2], &params, true);
+            }
+            // The irc crate calls RPL_ISUPPORT by its old name.
+            Response(RPL_BOUNCE, ref args, _) => {
+                for token in args.iter().skip(1) {
+                    if token.starts_with("PREFIX=") {
+                        if let Some(prefixes) = Prefixes::parse(&token["PREFIX=".len()..]) {
+                            self.prefixes = prefixes;
+                        }
+                    }
+                }
+                window_position = WindowPosition::Status;
+            }
+            Response(RPL_WHOISUSER, ref args, _) if args.len() >= 4 => {
+                self.users.set_host(&args[1], &format!("{}@{}", args[2], args[3]));
+                window_position = WindowPosition::Status;
+            }
+            Response(RPL_NAMREPLY, ref args, ref names) => {
+                let channel = args.last().map(|x| &x[..]).unwrap_or("");
+                match self.get_index_by_name(channel) {
+                    Some(index) => {
+                        let nicks = &mut self.windows[index].nicks;
+                        for nick in names.as_ref().map(|x| &x[..]).unwrap_or("").split_whitespace() {
+                            nicks.add(nick, &self.prefixes);
+                        }
+                        window_position = WindowPosition::Other(index);
+                    }
+                    None => window_position = WindowPosition::Status,
+                }
+            }
             _ => {
                 window_position = WindowPosition::Status;
             }
         }
-        let window = self.window_by_position(window_position);
-        window.show_event(&event);
-        self.current_window().update_display();
+        window_position
+    }
+
+    /// Returns the channel's window, or the Status window if there's none.
+    fn set_topic(&mut self, channel: &str, topic: Option<String>) -> WindowPosition {
+        match self.get_index_by_name(channel) {
+            Some(index) => {
+                self.windows[index].topic = match topic {
+                    Some(ref topic) if topic.is_empty() => None,
+                    topic => topic,
+                };
+                WindowPosition::Other(index)
+            }
+            None => WindowPosition::Status,
+        }
+    }
+
+    /// Applies a MODE change to a channel and the prefixes in its nick list,
+    /// or replaces its modes with those of an RPL_CHANNELMODEIS.
+    fn change_modes(&mut self, channel: &str, modes: &str, params: &[&str], replace: bool) -> WindowPosition {
+        match self.get_index_by_name(channel) {
+            Some(index) => {
+                let window = &mut self.windows[index];
+                if replace {
+                    window.modes.set(modes, params);
+                } else {
+                    for (adding, mode, nick) in window.modes.apply(modes, params, &self.prefixes) {
+                        if let Some(symbol) = self.prefixes.symbol(mode) {
+                            window.nicks.set_prefix(&nick, symbol, adding, &self.prefixes);
+                        }
+                    }
+                }
+                WindowPosition::Other(index)
+            }
+            None => WindowPosition::Status,
+        }
+    }
+
+    fn show_event_at(&self, position: WindowPosition, event: &event::ChatEvent) {
+        use irc_lib::client::data::Command::Response;
+        use irc_lib::client::data::Response::RPL_ENDOFWHOIS;
+        let window = self.window_by_position(position);
+        if let Response(RPL_ENDOFWHOIS, ref args, _) = event.message.command {
+            let nick = args.get(1).map(|x| &x[..]).unwrap_or("");
+            if let Some(account) = self.users.account(nick) {
+                window.display.add_message(format!("{} is logged in as {}", nick, account));
+            }
+        }
+        window.show_event(event);
+    }
+
+    /// TAGMSGs carry only client tags, such as typing notifications and
+    /// reactions, so they're never displayed on their own.
+    fn handle_tagmsg(&mut self, event: &event::ChatEvent, target: &str) {
+        let source = event.source_nickname().unwrap_or("");
+        let name = if is_channel(target) || event.about_self { target } else { source };
+        let index = match self.get_index_by_name(name) {
+            Some(index) => index,
+            None => return,
+        };
+        if let Some(state) = event.tags.get("+typing") {
+            if !event.about_self {
+                self.windows[index].typing.update(source, state);
+            }
+        }
+        let reaction = event.tags.get("+draft/react");
+        let msgid = event.tags.get("+draft/reply");
+        if let (Some(reaction), Some(msgid)) = (reaction, msgid) {
+            let window = &self.windows[index];
+            if window.display.react(msgid, reaction, source) && self.current_window == WindowPosition::Other(index) {
+                window.redraw();
+            }
+        }
+    }
+
+    /// Issues a label for a command sent from the current window, so that
+    /// labeled replies can be shown there.
+    pub fn new_label(&mut self) -> String {
+        self.next_label += 1;
+        let label = format!("{}", self.next_label);
+        let id = self.current_window().id().clone();
+        self.labels.insert(label.clone(), (id, Instant::now()));
+        label
+    }
+
+    /// Drops the labels still waiting for replies, which won't come once
+    /// the connection is gone.
+    pub fn forget_labels(&mut self) {
+        self.labels.clear();
+    }
+
+    fn labeled_position(&mut self, label: &str) -> Option<WindowPosition> {
+        let id = match self.labels.remove(label) {
+            Some((id, _)) => id,
+            None => return None,
+        };
+        match id.name() {
+            Some(name) => self.get_index_by_name(name).map(WindowPosition::Other),
+            None => Some(WindowPosition::Status),
+        }
+    }
+
+    pub fn set_loading(&self, name: &str) {
+        if let Some(index) = self.get_index_by_name(name) {
+            let window = &self.windows[index];
+            window.display.set_loading(true);
+            if self.current_window == WindowPosition::Other(index) {
+                window.display.redraw();
+            }
+        }
+    }
+
+    /// Gives up on history and labeled replies that have taken too long to
+    /// come.
+    pub fn expire_loading(&mut self) {
+        for window in &self.windows {
+            if window.display.loading_for().map_or(false, |time| time >= Duration::from_secs(CHATHISTORY_TIMEOUT)) {
+                window.display.set_loading(false);
+                window.display.add_message("No history came from the server.");
+            }
+        }
+        self.labels.retain(|_, &mut (_, issued)| issued.elapsed() < Duration::from_secs(LABEL_TIMEOUT));
+    }
+
+    /// The server couldn't send history. There's no telling which request
+    /// failed, so every window stops waiting.
+    fn stop_loading(&self) {
+        for window in &self.windows {
+            if window.display.is_loading() {
+                window.display.set_loading(false);
+            }
+        }
+    }
+
+    pub fn show_names(&self) {
+        let window = self.current_window();
+        let mut text = format!("Users on {} ({}): ", window.name(), window.nicks.len());
+        let mut positions = Vec::with_capacity(window.nicks.len());
+        for (i, nick) in window.nicks.iter().enumerate() {
+            if i > 0 {
+                text.push_str(", ");
+            }
+            text.push_str(&nick.prefix);
+            positions.push((text.len(), text.len() + nick.name.len()));
+            text.push_str(&nick.name);
+            if let Some(account) = self.users.account(&nick.name) {
+                text.push_str(&format!(" [{}]", account));
+            }
+            if self.users.away(&nick.name).is_some() {
+                text.push_str(" (away)");
+            }
+        }
+        window.display.add_message_with_nicks(text, positions);
+        window.redraw();
     }
 
     fn get_index_by_name(&self, name: &str) -> Option<usize> {
@@ -225,9 +1091,9 @@ impl Windows {
         }
         let name_owned = String::from(name);
         let window = if is_query {
-            Window::new(WindowId::Query { name: name_owned })
+            Window::new(WindowId::Query { name: name_owned }, self.settings.clone(), self.layout.clone())
         } else {
-            Window::new(WindowId::Channel { name: name_owned })
+            Window::new(WindowId::Channel { name: name_owned }, self.settings.clone(), self.layout.clone())
         };
         window.display.add_message(name);
         self.windows.push(window);
@@ -247,9 +1113,73 @@ impl Windows {
         Ok(())
     }
 
+    /// The open windows, in order, for saving the session.
+    pub fn saved(&self, network: &str) -> Vec<SavedWindow> {
+        self.windows.iter().filter_map(|window| {
+            let (kind, name) = match window.id {
+                WindowId::Channel { ref name } => (Kind::Channel, name),
+                WindowId::Query { ref name } => (Kind::Query, name),
+                WindowId::Status => return None,
+            };
+            Some(SavedWindow {
+                network: String::from(network),
+                kind: kind,
+                name: name.clone(),
+                read_until_ms: window.display.read_until(),
+            })
+        }).collect()
+    }
+
+    /// Reopens the windows of a saved session, after any already open.
+    pub fn restore<'a, I: Iterator<Item = &'a SavedWindow>>(&mut self, saved: I) {
+        for saved in saved {
+            if self.get_index_by_name(&saved.name).is_some() {
+                continue;
+            }
+            let name = saved.name.clone();
+            let id = match saved.kind {
+                Kind::Channel => WindowId::Channel { name: name },
+                Kind::Query => WindowId::Query { name: name },
+            };
+            let window = Window::new(id, self.settings.clone(), self.layout.clone());
+            window.display.set_read_until(saved.read_until_ms);
+            self.windows.push(window);
+        }
+    }
+
+    /// The channels with a window open, to join when connecting.
+    pub fn channels(&self) -> Vec<String> {
+        self.windows.iter().filter_map(|window| match window.id {
+            WindowId::Channel { ref name } => Some(name.clone()),
+            _ => None,
+        }).collect()
+    }
+
+    /// Every window, the Status window first.
+    pub fn iter<'a>(&'a self) -> Box<Iterator<Item = &'a Window> + 'a> {
+        Box::new(Some(&self.status).into_iter().chain(self.windows.iter()))
+    }
+
     pub fn activity<'a>(&'a self) -> Box<Iterator<Item = (usize, ActivityLevel)> + 'a> {
         let iter = Some(self.status.active.get()).into_iter();
         let iter = iter.chain(self.windows.iter().map(|w| w.active.get())).enumerate();
         Box::new(iter)
     }
 }
+
+/// Whether the letters of `needle` appear in `haystack` in order.
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+    needle.chars().all(|ch| haystack.any(|x| x == ch))
+}
+
+/// Whether the event is the server refusing a CHATHISTORY request, with a
+/// FAIL or an error numeric.
+fn is_history_error(event: &event::ChatEvent) -> bool {
+    use irc_lib::client::data::Command::{Raw, Response};
+    match event.message.command {
+        Raw(ref command, ref args, _) if command == "FAIL" => args.get(0).map_or(false, |x| x == "CHATHISTORY"),
+        Response(ref response, ref args, _) if response.is_error() => args.iter().any(|x| x == "CHATHISTORY"),
+        _ => false,
+    }
+}